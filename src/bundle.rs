@@ -0,0 +1,339 @@
+//! Bundle mode - every file due for archiving in a run goes into one shared archive
+//!
+//! Bundles are named `logrotate-bundle[-<group>]_YYYY_MM_DD.<ext>` and written to the top of the rotated
+//! directory, with entries keeping their path relative to it. The name follows the regular archive scheme, so
+//! keep-days, keep-count and the disk budget handle each bundle as a single archive of its own log family
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use tar::Builder;
+
+use crate::catalog::{self, ContentStats};
+use crate::crypto::{self, ArchiveSink};
+use crate::parallel::GzipWriter;
+use crate::redact::RedactionCounts;
+use crate::{
+    Action, ActionRecord, ArchiveType, LowSpaceAction, RunOptions, append_tar_entry, append_zip_entry, archive_entry_digests,
+    check_free_space_for, copy_existing_tar_entries, existing_entry_names, file_digest, get_date, get_file_extension, manifest,
+    open_zip_writer, redacted_digest, truncate_file, unique_entry_name, write_atomically,
+};
+
+/// Name every bundle starts with
+pub const BUNDLE_PREFIX: &str = "logrotate-bundle";
+
+/// How files are split across bundles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
+pub enum BundleGroup {
+    /// One bundle for the whole run
+    #[default]
+    None,
+    /// One bundle per file type, e.g. logrotate-bundle-log_...
+    FileType,
+    /// One bundle per subdirectory, e.g. logrotate-bundle-nginx_... - files at the top level go into logrotate-bundle_...
+    Subdirectory,
+}
+
+/// Where bundles are written and how files are grouped into them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleOptions {
+    /// Directory being rotated - bundles are written here and entry names are relative to it
+    pub directory: PathBuf,
+    pub group: BundleGroup,
+}
+
+/// Entry name of a provided file inside a bundle - its path relative to the rotated directory
+pub fn bundle_entry_name(directory: &Path, file: &Path) -> String {
+    file.strip_prefix(directory)
+        .unwrap_or(file)
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Group a provided file falls into, empty for the ungrouped bundle
+pub fn bundle_key(directory: &Path, file: &Path, group: BundleGroup) -> String {
+    match group {
+        BundleGroup::None => String::new(),
+        BundleGroup::FileType => get_file_extension(&file.to_string_lossy()),
+        BundleGroup::Subdirectory => file
+            .strip_prefix(directory)
+            .unwrap_or(file)
+            .parent()
+            .map(|parent| {
+                parent
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().to_string())
+                    .collect::<Vec<String>>()
+                    .join("-")
+            })
+            .unwrap_or_default(),
+    }
+}
+
+/// Destination path of the bundle for a provided group key, e.g. `./logs/logrotate-bundle-log_2025_09_26.tar.gz`
+pub fn bundle_path_for(directory: &Path, key: &str, archive_type: &ArchiveType, options: &RunOptions) -> PathBuf {
+    let mut name = if key.is_empty() { BUNDLE_PREFIX.to_string() } else { format!("{}-{}", BUNDLE_PREFIX, key) };
+    name = name + "_" + &get_date() + "." + archive_type.as_str();
+    if !options.recipients.is_empty() {
+        name += crypto::ENCRYPTED_SUFFIX;
+    }
+    directory.join(name)
+}
+
+/// Split files into bundles by group key, keeping their order within each bundle
+pub fn plan_bundles(directory: &Path, file_list: &[PathBuf], group: BundleGroup) -> BTreeMap<String, Vec<PathBuf>> {
+    let mut bundles: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for file in file_list {
+        bundles.entry(bundle_key(directory, file, group)).or_default().push(file.clone());
+    }
+    bundles
+}
+
+/// Entry names of the provided files in a bundle - relative paths, made unique against the entries already in
+/// the bundle in append mode
+pub fn bundle_entry_names(bundle_path: &Path, directory: &Path, file_list: &[PathBuf], options: &RunOptions) -> anyhow::Result<Vec<String>> {
+    let mut taken = if options.append { existing_entry_names(bundle_path)? } else { Vec::new() };
+    let mut entry_names = Vec::new();
+    for file in file_list {
+        let entry_name = unique_entry_name(&bundle_entry_name(directory, file), &taken);
+        taken.push(entry_name.clone());
+        entry_names.push(entry_name);
+    }
+    Ok(entry_names)
+}
+
+/// Write one bundle holding every provided (file, entry name), encrypting and redacting as `options` asks
+/// In append mode the entries of an existing bundle are carried over. Returns the redaction counts of each file, in order
+pub fn write_bundle(bundle_path: &Path, entries: &[(PathBuf, String)], archive_type: &ArchiveType, options: &RunOptions) -> anyhow::Result<Vec<RedactionCounts>> {
+    let mut counts = Vec::new();
+
+    write_atomically(bundle_path, |bundle_file| {
+        let sink = ArchiveSink::new(bundle_file, &options.recipients)?;
+        match archive_type {
+            ArchiveType::Tar => {
+                let mut tar_builder = Builder::new(sink);
+                if options.append {
+                    copy_existing_tar_entries(bundle_path, archive_type, &mut tar_builder)?;
+                }
+                for (file, entry_name) in entries {
+                    counts.push(append_tar_entry(&mut tar_builder, file, entry_name, options)?);
+                }
+                Ok(tar_builder.into_inner()?.finish()?)
+            }
+            ArchiveType::TarGunzip => {
+                let mut total_size = 0;
+                for (file, _) in entries {
+                    total_size += fs::metadata(file)?.len();
+                }
+                let threads = options.parallel_compression.and_then(|parallel| parallel.threads_for(total_size));
+                let mut tar_builder = Builder::new(GzipWriter::new(sink, None, 0, threads));
+                if options.append {
+                    copy_existing_tar_entries(bundle_path, archive_type, &mut tar_builder)?;
+                }
+                for (file, entry_name) in entries {
+                    counts.push(append_tar_entry(&mut tar_builder, file, entry_name, options)?);
+                }
+                Ok(tar_builder.into_inner()?.finish()?.finish()?)
+            }
+            ArchiveType::Zip => match sink {
+                ArchiveSink::Plain(zip_file) => {
+                    let mut zip_builder = open_zip_writer(bundle_path, zip_file, options)?;
+                    for (file, entry_name) in entries {
+                        counts.push(append_zip_entry(&mut zip_builder, file, entry_name, options)?);
+                    }
+                    Ok(zip_builder.finish()?)
+                }
+                sink => {
+                    let mut zip_builder = zip::ZipWriter::new_stream(sink);
+                    for (file, entry_name) in entries {
+                        counts.push(append_zip_entry(&mut zip_builder, file, entry_name, options)?);
+                    }
+                    Ok(zip_builder.finish()?.into_inner().finish()?)
+                }
+            },
+            _ => Err(anyhow::anyhow!("Bundles need a tar, tar.gz or zip archive method")),
+        }
+    })?;
+    Ok(counts)
+}
+
+/// Archive every provided file into its bundle, then truncate the sources
+/// A bundle that already exists (e.g. from an earlier run the same day) is appended to rather than overwritten.
+/// Sources are only truncated once their whole bundle has been written (and verified / recorded when asked to),
+/// so a failed bundle leaves every file in it untouched. Returns one record per file, in order
+pub fn bundle_files(directory: &Path, file_list: &[PathBuf], group: BundleGroup, archive_type: ArchiveType, options: &RunOptions) -> Vec<ActionRecord> {
+    let mut records: BTreeMap<PathBuf, ActionRecord> = BTreeMap::new();
+
+    for (key, files) in plan_bundles(directory, file_list, group) {
+        let bundle_path = bundle_path_for(directory, &key, &archive_type, options);
+        let mut group_records: Vec<ActionRecord> = files.iter().map(|file| ActionRecord::new(file, Action::Archive)).collect();
+
+        match write_bundle_group(&bundle_path, directory, &files, &archive_type, options) {
+            Ok(BundleOutcome::Written(redactions)) => {
+                for (record, redactions) in group_records.iter_mut().zip(redactions) {
+                    record.archive = Some(bundle_path.clone());
+                    record.redactions = redactions;
+                }
+            }
+            Ok(BundleOutcome::LowSpace(action, reason)) => {
+                for record in group_records.iter_mut() {
+                    record.action = action;
+                    record.reason = Some(reason.clone());
+                }
+            }
+            Err(error) => {
+                for record in group_records.iter_mut() {
+                    record.action = Action::Skipped;
+                    record.reason = Some(format!("{:#}", error));
+                }
+            }
+        }
+
+        for record in group_records {
+            records.insert(record.file.clone(), record);
+        }
+    }
+
+    file_list
+        .iter()
+        .filter_map(|file| records.remove(file))
+        .collect()
+}
+
+/// What happened to one bundle
+enum BundleOutcome {
+    Written(Vec<RedactionCounts>),
+    /// Not written because of the free space guard - files were skipped or emergency truncated
+    LowSpace(Action, String),
+}
+
+fn write_bundle_group(bundle_path: &Path, directory: &Path, file_list: &[PathBuf], archive_type: &ArchiveType, options: &RunOptions) -> anyhow::Result<BundleOutcome> {
+    if options.verify && !options.recipients.is_empty() {
+        return Err(anyhow::anyhow!("Encrypted archives cannot be verified without the private key"));
+    }
+    if options.append && !options.recipients.is_empty() {
+        return Err(anyhow::anyhow!("Encrypted archives cannot be appended to without the private key"));
+    }
+    // A bundle already written today is added to, never replaced - its entries keep their names and new ones get a suffix
+    let appending_options;
+    let options = if !options.append && bundle_path.exists() {
+        if !options.recipients.is_empty() {
+            return Err(anyhow::anyhow!("Bundle '{}' already exists and encrypted archives cannot be appended to", bundle_path.display()));
+        }
+        appending_options = RunOptions { append: true, ..options.clone() };
+        &appending_options
+    } else {
+        options
+    };
+
+    if let Some(guard) = options.free_space_guard {
+        let mut required = 0;
+        for file in file_list {
+            required += fs::metadata(file)?.len() + 10240;
+        }
+        if let Some(reason) = check_free_space_for(directory, required, guard.reserve)? {
+            let action = match guard.on_low_space {
+                LowSpaceAction::Skip => Action::Skipped,
+                LowSpaceAction::Emergency => {
+                    for file in file_list {
+                        truncate_file(file)
+                            .map_err(|error| anyhow::anyhow!("Unable to truncate '{}': {}", file.display(), error))?;
+                    }
+                    Action::Truncate
+                }
+            };
+            return Ok(BundleOutcome::LowSpace(action, reason));
+        }
+    }
+
+    let entries: Vec<(PathBuf, String)> = file_list
+        .iter()
+        .cloned()
+        .zip(bundle_entry_names(bundle_path, directory, file_list, options)?)
+        .collect();
+
+    // Entry name -> expected (length, sha256) of what goes into the bundle, and the source snapshots for the manifest
+    let mut expected_entries: BTreeMap<String, (u64, String)> = BTreeMap::new();
+    let mut source_hasher = Sha256::new();
+    if options.verify || options.manifest {
+        for (file, entry_name) in &entries {
+            let entry_name = entry_name.clone();
+            let source_snapshot = file_digest(file)?;
+            source_hasher.update(format!("{}\t{}\n", entry_name, source_snapshot.1));
+            let expected_entry = match &options.redactor {
+                Some(redactor) => redacted_digest(file, redactor)?.1,
+                None => source_snapshot,
+            };
+            expected_entries.insert(entry_name, expected_entry);
+        }
+    }
+
+    let mut content_stats = ContentStats::default();
+    for file in file_list {
+        content_stats.merge(&catalog::scan_file(file)?);
+    }
+
+    let redactions = write_bundle(bundle_path, &entries, archive_type, options)
+        .map_err(|error| error.context(format!("Failed to write bundle '{}'", bundle_path.display())))?;
+
+    // A bundle mixes several owners' files - it takes the most restrictive of their modes unless told otherwise
+    let mode = match options.archive_mode {
+        Some(mode) => mode,
+        None => {
+            let mut mode = 0o777;
+            for file in file_list {
+                mode &= fs::metadata(file)?.permissions().mode();
+            }
+            mode
+        }
+    };
+    fs::set_permissions(bundle_path, fs::Permissions::from_mode(mode))?;
+
+    if options.verify
+        && let Err(error) = verify_bundle(bundle_path, &expected_entries, options.append)
+    {
+        // A bundle appended to also holds entries from earlier runs - keep it
+        if !options.append {
+            fs::remove_file(bundle_path).ok();
+        }
+        return Err(error.context(format!("Verification of '{}' failed, sources left untouched", bundle_path.display())));
+    }
+
+    if options.manifest {
+        manifest::record_archive(bundle_path, &format!("{:x}", source_hasher.finalize()))
+            .map_err(|error| error.context(format!("Failed to record '{}' in the manifest, sources left untouched", bundle_path.display())))?;
+    }
+    catalog::record_archive(bundle_path, file_list, &content_stats, options.append)
+        .map_err(|error| error.context(format!("Failed to record '{}' in the catalog, sources left untouched", bundle_path.display())))?;
+
+    for file in file_list {
+        truncate_file(file)
+            .map_err(|error| anyhow::anyhow!("Written to '{}' but failed to truncate '{}': {}", bundle_path.display(), file.display(), error))?;
+        if let Some(create_mode) = options.create_mode {
+            fs::set_permissions(file, fs::Permissions::from_mode(create_mode))?;
+        }
+    }
+    Ok(BundleOutcome::Written(redactions))
+}
+
+/// Check that a freshly written bundle holds the expected entries - and nothing else, unless it was appended to
+pub fn verify_bundle(bundle_path: &Path, expected_entries: &BTreeMap<String, (u64, String)>, appended: bool) -> anyhow::Result<()> {
+    let digests = archive_entry_digests(bundle_path)?;
+    if !appended && digests.len() != expected_entries.len() {
+        return Err(anyhow::anyhow!("Expected {} entries in '{}', found {}", expected_entries.len(), bundle_path.display(), digests.len()));
+    }
+
+    for (name, (length, sha256)) in expected_entries {
+        let Some(entry) = digests.iter().find(|entry| &entry.name == name) else {
+            return Err(anyhow::anyhow!("No entry '{}' in '{}'", name, bundle_path.display()));
+        };
+        if entry.length != *length || &entry.sha256 != sha256 {
+            return Err(anyhow::anyhow!("Entry '{}' does not match its source snapshot", entry.name));
+        }
+    }
+    Ok(())
+}
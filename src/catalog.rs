@@ -0,0 +1,326 @@
+//! Per-directory archive catalog
+//!
+//! `.logrotate-catalog` in each archive directory holds one JSON line per archive: the files that went into it, the
+//! time range of their log lines, sizes, line count and checksum. Every rotation, numbered shift and retention removal
+//! keeps it current, so `list`, `grep` and retention can tell what an archive holds without opening it
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{file_digest, write_atomically};
+
+/// Name of the catalog file kept in each archive directory
+pub const CATALOG_FILE_NAME: &str = ".logrotate-catalog";
+
+/// Lines searched for a timestamp at the start and at the end of a file
+pub const TIMESTAMP_SCAN_LINES: usize = 100;
+
+/// Held while a catalog is read, updated and written back
+static CATALOG_LOCK: Mutex<()> = Mutex::new(());
+
+/// One archive of the catalog
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    /// Archive file name, relative to the catalog's directory
+    pub archive: String,
+    /// Files that went into the archive
+    pub sources: Vec<String>,
+    /// RFC 3339 - when the archive was written or last appended to
+    pub rotated: String,
+    /// RFC 3339 - first and last timestamp found in the log lines, None when no line carries one
+    pub first_timestamp: Option<String>,
+    pub last_timestamp: Option<String>,
+    /// Size of the content before compression
+    pub original_bytes: u64,
+    pub archive_bytes: u64,
+    pub lines: u64,
+    /// original_bytes / archive_bytes
+    pub compression_ratio: f64,
+    /// SHA-256 of the archive file
+    pub sha256: String,
+}
+
+impl CatalogEntry {
+    pub fn rotated_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.rotated).ok().map(|time| time.to_utc())
+    }
+
+    pub fn first_time(&self) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_rfc3339(self.first_timestamp.as_deref()?).ok()
+    }
+
+    pub fn last_time(&self) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_rfc3339(self.last_timestamp.as_deref()?).ok()
+    }
+}
+
+/// What was read out of the content going into an archive
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentStats {
+    pub bytes: u64,
+    pub lines: u64,
+    pub first_timestamp: Option<DateTime<FixedOffset>>,
+    pub last_timestamp: Option<DateTime<FixedOffset>>,
+}
+
+impl ContentStats {
+    /// Combine the stats of several files going into one archive
+    pub fn merge(&mut self, other: &ContentStats) {
+        self.bytes += other.bytes;
+        self.lines += other.lines;
+        self.first_timestamp = match (self.first_timestamp, other.first_timestamp) {
+            (Some(ours), Some(theirs)) => Some(ours.min(theirs)),
+            (ours, theirs) => ours.or(theirs),
+        };
+        self.last_timestamp = match (self.last_timestamp, other.last_timestamp) {
+            (Some(ours), Some(theirs)) => Some(ours.max(theirs)),
+            (ours, theirs) => ours.or(theirs),
+        };
+    }
+}
+
+fn iso_timestamp() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"(\d{4}-\d{2}-\d{2})[T ](\d{2}:\d{2}:\d{2})(?:[.,]\d+)?(Z| ?[+-]\d{2}:?\d{2})?").unwrap()
+    })
+}
+
+fn common_log_timestamp() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"\d{2}/[A-Z][a-z]{2}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4}").unwrap())
+}
+
+fn syslog_timestamp() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"^([A-Z][a-z]{2}) +(\d{1,2}) (\d{2}:\d{2}:\d{2})").unwrap())
+}
+
+fn local_time(naive: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    Local.from_local_datetime(&naive).earliest().map(|time| time.fixed_offset())
+}
+
+/// Timestamp of a log line - ISO 8601 / RFC 3339 anywhere in the line, the common log format
+/// (`26/Sep/2025:03:00:00 +0000`) or a BSD syslog prefix (`Sep 26 03:00:00`, which has no year of its own)
+/// Times without an offset are local
+pub fn parse_log_timestamp(line: &str, year: i32) -> Option<DateTime<FixedOffset>> {
+    if let Some(captures) = iso_timestamp().captures(line) {
+        let naive = NaiveDateTime::parse_from_str(&format!("{} {}", &captures[1], &captures[2]), "%Y-%m-%d %H:%M:%S").ok()?;
+        return match captures.get(3).map(|offset| offset.as_str()) {
+            None => local_time(naive),
+            Some("Z") => Some(naive.and_utc().fixed_offset()),
+            Some(offset) => {
+                let digits = offset.trim_start().replace(':', "");
+                let seconds = digits[1..3].parse::<i32>().ok()? * 3600 + digits[3..5].parse::<i32>().ok()? * 60;
+                let offset = if digits.starts_with('-') { FixedOffset::west_opt(seconds)? } else { FixedOffset::east_opt(seconds)? };
+                offset.from_local_datetime(&naive).single()
+            }
+        };
+    }
+    if let Some(found) = common_log_timestamp().find(line) {
+        return DateTime::parse_from_str(found.as_str(), "%d/%b/%Y:%H:%M:%S %z").ok();
+    }
+    let captures = syslog_timestamp().captures(line)?;
+    let naive = NaiveDateTime::parse_from_str(&format!("{} {} {} {}", year, &captures[1], &captures[2], &captures[3]), "%Y %b %d %H:%M:%S").ok()?;
+    local_time(naive)
+}
+
+/// Count the bytes and lines of a reader and find the first and last timestamp
+/// Only the first and last `TIMESTAMP_SCAN_LINES` lines are parsed
+pub fn scan_content<R: Read>(reader: R, year: i32) -> io::Result<ContentStats> {
+    let mut reader = io::BufReader::new(reader);
+    let mut stats = ContentStats::default();
+    let mut tail: VecDeque<Vec<u8>> = VecDeque::with_capacity(TIMESTAMP_SCAN_LINES);
+    loop {
+        let mut line = match tail.len() {
+            TIMESTAMP_SCAN_LINES => tail.pop_front().unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        stats.bytes += read as u64;
+        stats.lines += 1;
+        if stats.first_timestamp.is_none() && stats.lines <= TIMESTAMP_SCAN_LINES as u64 {
+            stats.first_timestamp = parse_log_timestamp(&String::from_utf8_lossy(&line), year);
+        }
+        tail.push_back(line);
+    }
+    stats.last_timestamp = tail
+        .iter()
+        .rev()
+        .find_map(|line| parse_log_timestamp(&String::from_utf8_lossy(line), year));
+    Ok(stats)
+}
+
+/// Scan a file up to the length it has now - syslog timestamps take the year of its mtime
+pub fn scan_file(file_path: &Path) -> anyhow::Result<ContentStats> {
+    let file = fs::File::open(file_path)
+        .map_err(|error| anyhow::anyhow!("Unable to open '{}': {}", file_path.display(), error))?;
+    let metadata = file.metadata()?;
+    let modified: DateTime<Local> = metadata.modified()?.into();
+    Ok(scan_content(file.take(metadata.len()), modified.year())?)
+}
+
+/// Catalog file of a provided directory
+pub fn catalog_path(dir_path: &Path) -> PathBuf {
+    dir_path.join(CATALOG_FILE_NAME)
+}
+
+/// Directory holding a provided archive, `.` for bare file names
+fn archive_directory(archive_path: &Path) -> &Path {
+    archive_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+fn archive_name(archive_path: &Path) -> String {
+    archive_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Read every entry of a directory's catalog - empty when there is no catalog yet
+pub fn read_catalog(dir_path: &Path) -> anyhow::Result<Vec<CatalogEntry>> {
+    let path = catalog_path(dir_path);
+    let catalog = match fs::read_to_string(&path) {
+        Ok(catalog) => catalog,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(anyhow::anyhow!("Unable to read catalog '{}': {}", path.display(), error)),
+    };
+
+    catalog
+        .lines()
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .map_err(|error| anyhow::anyhow!("Malformed catalog '{}' line {}: {}", path.display(), index + 1, error))
+        })
+        .collect()
+}
+
+fn write_catalog(dir_path: &Path, entries: &[CatalogEntry]) -> anyhow::Result<()> {
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&serde_json::to_string(entry)?);
+        contents.push('\n');
+    }
+    write_atomically(&catalog_path(dir_path), |mut file| {
+        file.write_all(contents.as_bytes())?;
+        Ok(file)
+    })
+}
+
+/// Read, change and write back the catalog of an archive's directory
+fn update_catalog<F>(archive_path: &Path, update: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut Vec<CatalogEntry>) -> anyhow::Result<()>,
+{
+    let _guard = CATALOG_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let dir_path = archive_directory(archive_path);
+    let mut entries = read_catalog(dir_path)?;
+    update(&mut entries)?;
+    write_catalog(dir_path, &entries)
+}
+
+/// Record a freshly written archive - an archive appended to keeps its earlier sources, lines and time range
+pub fn record_archive(archive_path: &Path, sources: &[PathBuf], stats: &ContentStats, appended: bool) -> anyhow::Result<CatalogEntry> {
+    let (archive_bytes, sha256) = file_digest(archive_path)?;
+    let name = archive_name(archive_path);
+    let mut recorded = CatalogEntry::default();
+    update_catalog(archive_path, |entries| {
+        let previous = entries.iter().position(|entry| entry.archive == name).map(|index| entries.remove(index));
+        let mut merged = stats.clone();
+        let mut all_sources = Vec::new();
+        if let Some(previous) = previous.filter(|_| appended) {
+            merged.merge(&ContentStats {
+                bytes: previous.original_bytes,
+                lines: previous.lines,
+                first_timestamp: previous.first_time(),
+                last_timestamp: previous.last_time(),
+            });
+            all_sources = previous.sources;
+        }
+        for source in sources {
+            let source = fs::canonicalize(source).unwrap_or_else(|_| source.clone()).display().to_string();
+            if !all_sources.contains(&source) {
+                all_sources.push(source);
+            }
+        }
+
+        recorded = CatalogEntry {
+            archive: name.clone(),
+            sources: all_sources,
+            rotated: Local::now().to_rfc3339_opts(SecondsFormat::Secs, false),
+            first_timestamp: merged.first_timestamp.map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, false)),
+            last_timestamp: merged.last_timestamp.map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, false)),
+            original_bytes: merged.bytes,
+            archive_bytes,
+            lines: merged.lines,
+            compression_ratio: if archive_bytes == 0 { 0.0 } else { merged.bytes as f64 / archive_bytes as f64 },
+            sha256,
+        };
+        entries.push(recorded.clone());
+        Ok(())
+    })?;
+    Ok(recorded)
+}
+
+/// Follow an archive renamed in place, e.g. `app.log.1.tar.gz` shifted to `app.log.2.tar.gz`
+pub fn record_rename(old_path: &Path, new_path: &Path) -> anyhow::Result<()> {
+    let (old_name, new_name) = (archive_name(old_path), archive_name(new_path));
+    update_catalog(new_path, |entries| {
+        entries.retain(|entry| entry.archive != new_name);
+        if let Some(entry) = entries.iter_mut().find(|entry| entry.archive == old_name) {
+            entry.archive = new_name;
+        }
+        Ok(())
+    })
+}
+
+/// Forget a removed archive
+pub fn remove_entry(archive_path: &Path) -> anyhow::Result<()> {
+    if !catalog_path(archive_directory(archive_path)).exists() {
+        return Ok(());
+    }
+    let name = archive_name(archive_path);
+    update_catalog(archive_path, |entries| {
+        entries.retain(|entry| entry.archive != name);
+        Ok(())
+    })
+}
+
+/// Catalog entries of archives spread over any number of directories, each catalog read once
+/// A catalog that cannot be read counts as empty - callers fall back on archive names and mtimes
+#[derive(Debug, Clone, Default)]
+pub struct CatalogIndex {
+    directories: BTreeMap<PathBuf, BTreeMap<String, CatalogEntry>>,
+}
+
+impl CatalogIndex {
+    pub fn lookup(&mut self, archive_path: &Path) -> Option<&CatalogEntry> {
+        let directory = archive_directory(archive_path).to_path_buf();
+        self.directories
+            .entry(directory)
+            .or_insert_with_key(|directory| {
+                read_catalog(directory)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|entry| (entry.archive.clone(), entry))
+                    .collect()
+            })
+            .get(&archive_name(archive_path))
+    }
+}
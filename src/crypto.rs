@@ -0,0 +1,107 @@
+//! Archive encryption at rest with age X25519 recipients
+//!
+//! Archives are encrypted as they are written - the tar / gzip / zip stream goes straight into an age stream
+//! on top of the destination file, so no plaintext copy of the archive ever touches the disk
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use age::x25519;
+
+use crate::write_atomically;
+
+/// Suffix appended to the name of encrypted archives, e.g. `app.log_2025_09_26.tar.gz.age`
+pub const ENCRYPTED_SUFFIX: &str = ".age";
+
+/// Parse an age X25519 recipient (`age1...`)
+pub fn parse_recipient(recipient: &str) -> anyhow::Result<x25519::Recipient> {
+    x25519::Recipient::from_str(recipient.trim())
+        .map_err(|error| anyhow::anyhow!("Invalid age recipient '{}': {}", recipient, error))
+}
+
+/// Destination an archive backend writes into - the file itself, or an age stream on top of it
+pub enum ArchiveSink {
+    Plain(fs::File),
+    Encrypted(age::stream::StreamWriter<fs::File>),
+}
+
+impl ArchiveSink {
+    /// Encrypt to every provided recipient, or pass the file through untouched when there are none
+    pub fn new(file: fs::File, recipients: &[x25519::Recipient]) -> anyhow::Result<ArchiveSink> {
+        if recipients.is_empty() {
+            return Ok(ArchiveSink::Plain(file));
+        }
+
+        let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|recipient| recipient as &dyn age::Recipient))?;
+        Ok(ArchiveSink::Encrypted(encryptor.wrap_output(file)?))
+    }
+
+    /// Flush the final age chunk (when encrypting) and hand the destination file back
+    pub fn finish(self) -> io::Result<fs::File> {
+        match self {
+            ArchiveSink::Plain(file) => Ok(file),
+            ArchiveSink::Encrypted(writer) => writer.finish(),
+        }
+    }
+}
+
+impl Write for ArchiveSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ArchiveSink::Plain(file) => file.write(buf),
+            ArchiveSink::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ArchiveSink::Plain(file) => file.flush(),
+            ArchiveSink::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Load every identity (private key) from an age identity file
+pub fn load_identities(identity_file: &Path) -> anyhow::Result<Vec<Box<dyn age::Identity>>> {
+    let identities = age::IdentityFile::from_file(identity_file.to_string_lossy().to_string())
+        .map_err(|error| anyhow::anyhow!("Unable to read identity file '{}': {}", identity_file.display(), error))?
+        .into_identities()?;
+    if identities.is_empty() {
+        return Err(anyhow::anyhow!("No identities found in '{}'", identity_file.display()));
+    }
+    Ok(identities)
+}
+
+/// Wrap an encrypted stream in a reader yielding the plaintext archive
+pub fn decrypt_reader<R: Read>(reader: R, identities: &[Box<dyn age::Identity>]) -> anyhow::Result<impl Read> {
+    let decryptor = age::Decryptor::new(reader)?;
+    Ok(decryptor.decrypt(identities.iter().map(|identity| identity.as_ref()))?)
+}
+
+/// Decrypt an archive to `output`, or next to it without the `.age` suffix
+/// Refuses to overwrite an existing file. Returns the path of the decrypted archive
+pub fn decrypt_archive(archive_path: &Path, identity_file: &Path, output: Option<&Path>) -> anyhow::Result<PathBuf> {
+    let output_path = match output {
+        Some(output) => output.to_path_buf(),
+        None => {
+            let archive_str = archive_path.to_string_lossy();
+            let plain_name = archive_str
+                .strip_suffix(ENCRYPTED_SUFFIX)
+                .ok_or_else(|| anyhow::anyhow!("'{}' does not end in {} - pass an output path", archive_str, ENCRYPTED_SUFFIX))?;
+            PathBuf::from(plain_name)
+        }
+    };
+    if output_path.exists() {
+        return Err(anyhow::anyhow!("'{}' already exists", output_path.display()));
+    }
+
+    let identities = load_identities(identity_file)?;
+    let mut plaintext = decrypt_reader(fs::File::open(archive_path)?, &identities)?;
+    write_atomically(&output_path, |mut output_file| {
+        io::copy(&mut plaintext, &mut output_file)?;
+        Ok(output_file)
+    })?;
+    Ok(output_path)
+}
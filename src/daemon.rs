@@ -0,0 +1,410 @@
+//! Daemon mode - runs every target of a policy file on its own schedule
+//!
+//! SIGTERM / SIGINT let the file being archived finish, skip the rest of the run and exit. SIGHUP re-reads the
+//! policy - an invalid policy is reported and the previous one kept. Targets with a `max_size` are also watched
+//! with inotify and rotated as soon as a file grows past it - see `watch`
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+
+use crate::bundle::bundle_files;
+use crate::policy::{Target, load_policy_connected};
+use crate::state::{RunSummary, record_run};
+use crate::systemd::{Journal, PRIORITY_ERR, PRIORITY_INFO, notify, watchdog_interval};
+use crate::watch::{DirectoryWatcher, SIZE_DEBOUNCE, SizeTrigger, WatchEvent};
+use crate::{
+    ActionRecord, actual_run, apply_count_retention, apply_disk_budget, archive_with_guard, cleanup_stray_temp_files,
+    forward_record, gather_files_from_directory, gather_files_recursively, run_jobs, split_archive_name,
+};
+
+/// Set by SIGTERM / SIGINT
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+/// Set by SIGHUP
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+/// Longest sleep between checks for signals
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A target and the next time it is due
+#[derive(Debug, Clone)]
+pub struct ScheduledTarget {
+    pub target: Target,
+    pub next_run: DateTime<Local>,
+}
+
+/// Every target of the loaded policy with its next run
+#[derive(Debug, Clone, Default)]
+pub struct Scheduler {
+    pub entries: Vec<ScheduledTarget>,
+}
+
+impl Scheduler {
+    pub fn new(targets: Vec<Target>, now: DateTime<Local>) -> Scheduler {
+        let mut scheduler = Scheduler::default();
+        scheduler.reload(targets, now);
+        scheduler
+    }
+
+    /// Swap in a new set of targets - targets whose name and schedule did not change keep their next run
+    pub fn reload(&mut self, targets: Vec<Target>, now: DateTime<Local>) {
+        self.entries = targets
+            .into_iter()
+            .map(|target| {
+                let next_run = self
+                    .entries
+                    .iter()
+                    .find(|entry| entry.target.name == target.name && entry.target.schedule == target.schedule)
+                    .map(|entry| entry.next_run)
+                    .unwrap_or_else(|| target.schedule.next_after(now));
+                ScheduledTarget { target, next_run }
+            })
+            .collect();
+    }
+
+    /// Indexes of the targets due at `now`, in policy order
+    pub fn due(&self, now: DateTime<Local>) -> Vec<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.next_run <= now)
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Schedule a target's next run after one that finished at `finished`
+    pub fn mark_run(&mut self, index: usize, finished: DateTime<Local>) {
+        let entry = &mut self.entries[index];
+        entry.next_run = entry.target.schedule.next_after(finished);
+    }
+
+    pub fn next_wakeup(&self) -> Option<DateTime<Local>> {
+        self.entries.iter().map(|entry| entry.next_run).min()
+    }
+}
+
+/// One rotation of a target - the same passes as a command line run: rotation, then keep-count and disk budget
+pub fn run_target(target: &Target) -> anyhow::Result<Vec<ActionRecord>> {
+    for stray_file in cleanup_stray_temp_files(&target.directory)? {
+        log(&format!("[{}] Removed stray temp file from an interrupted run: {}", target.name, stray_file.display()));
+    }
+
+    let mut file_list = if target.recursive {
+        gather_files_recursively(&target.directory)?
+    }
+    else {
+        gather_files_from_directory(&target.directory)?
+    };
+    if target.count_only {
+        file_list.retain(|file| file.to_str().and_then(split_archive_name).is_none());
+    }
+
+    let started = Local::now();
+    let mut report = actual_run(file_list, target.keep_days.into(), target.archive_type.clone(), &target.options);
+    report.extend(apply_retention(target)?);
+    record_run(Path::new(&target.directory), RunSummary::from_report(started, Local::now(), &report))?;
+    Ok(report)
+}
+
+/// Archive files that grew past the target's `max_size` right away, whatever their age - bundled when the target
+/// bundles, otherwise on the target's worker pool like a regular run
+pub fn rotate_oversized(target: &Target, files: &[PathBuf]) -> anyhow::Result<Vec<ActionRecord>> {
+    let started = Local::now();
+    let mut report = match &target.options.bundle {
+        Some(bundle_options) => bundle_files(&bundle_options.directory, files, bundle_options.group, target.archive_type.clone(), &target.options),
+        None => run_jobs(files, target.options.jobs, |file| archive_with_guard(file, target.archive_type.clone(), &target.options)),
+    };
+    for record in &report {
+        forward_record(record, &target.options);
+    }
+    report.extend(apply_retention(target)?);
+    record_run(Path::new(&target.directory), RunSummary::from_report(started, Local::now(), &report))?;
+    Ok(report)
+}
+
+/// Keep-count and disk budget passes after a rotation - skipped once shutdown was requested
+fn apply_retention(target: &Target) -> anyhow::Result<Vec<ActionRecord>> {
+    let mut report = Vec::new();
+    if SHUTDOWN.load(Ordering::SeqCst) {
+        return Ok(report);
+    }
+    if let Some(keep_count) = target.keep_count {
+        report.extend(apply_count_retention(&target.directory, keep_count, &target.options, false)?);
+    }
+    if let Some(max_total_size) = target.max_total_size {
+        report.extend(apply_disk_budget(&target.directory, max_total_size, target.force_rotate, target.archive_type.clone(), &target.options, false)?);
+    }
+    Ok(report)
+}
+
+/// inotify watches and size triggers for every target with a `max_size`
+struct SizeWatch {
+    watcher: DirectoryWatcher,
+    /// Index of the target in the scheduler, with its trigger
+    triggers: Vec<(usize, SizeTrigger)>,
+}
+
+impl SizeWatch {
+    /// Watch the directories of the targets with a `max_size` - None when no target has one
+    /// Files already there are queued for a size check, so a file that outgrew its limit while the daemon was down
+    /// is rotated too
+    fn new(scheduler: &Scheduler) -> anyhow::Result<Option<SizeWatch>> {
+        let mut size_watch = SizeWatch { watcher: DirectoryWatcher::new()?, triggers: Vec::new() };
+        let now = Instant::now();
+        for (index, entry) in scheduler.entries.iter().enumerate() {
+            let Some(max_size) = entry.target.max_size else { continue };
+            size_watch.triggers.push((index, SizeTrigger::new(max_size, SIZE_DEBOUNCE, entry.target.rotate_cooldown)));
+            size_watch.watch_directory(scheduler, Path::new(&entry.target.directory), now)?;
+        }
+        Ok((!size_watch.triggers.is_empty()).then_some(size_watch))
+    }
+
+    /// Watch a directory (and its subdirectories for recursive targets) and queue the files in it
+    fn watch_directory(&mut self, scheduler: &Scheduler, directory: &Path, now: Instant) -> anyhow::Result<()> {
+        self.watcher.watch(directory)?;
+        let recursive = self.watches_subdirectories(scheduler, directory);
+        for entry in std::fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if recursive {
+                    self.watch_directory(scheduler, &path, now)?;
+                }
+            }
+            else {
+                self.notify(scheduler, &path, now);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a recursive target covers `directory`
+    fn watches_subdirectories(&self, scheduler: &Scheduler, directory: &Path) -> bool {
+        self.triggers.iter().any(|(index, _)| {
+            let target = &scheduler.entries[*index].target;
+            target.recursive && directory.starts_with(&target.directory)
+        })
+    }
+
+    /// Queue a written file with every target it belongs to
+    fn notify(&mut self, scheduler: &Scheduler, file: &Path, now: Instant) {
+        for (index, trigger) in self.triggers.iter_mut() {
+            let target = &scheduler.entries[*index].target;
+            let belongs = match target.recursive {
+                true => file.starts_with(&target.directory),
+                false => file.parent() == Some(Path::new(&target.directory)),
+            };
+            if belongs {
+                trigger.notify(file, now);
+            }
+        }
+    }
+
+    fn handle_events(&mut self, scheduler: &Scheduler, events: Vec<WatchEvent>) -> anyhow::Result<()> {
+        let now = Instant::now();
+        for event in events {
+            match event {
+                WatchEvent::File(file) => self.notify(scheduler, &file, now),
+                WatchEvent::Directory(directory) => {
+                    if self.watches_subdirectories(scheduler, &directory) {
+                        self.watch_directory(scheduler, &directory, now)?;
+                    }
+                }
+                WatchEvent::Overflow => {
+                    let indexes: Vec<usize> = self.triggers.iter().map(|(index, _)| *index).collect();
+                    for index in indexes {
+                        self.watch_directory(scheduler, Path::new(&scheduler.entries[index].target.directory), now)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rotate every due file past its target's `max_size`
+    fn rotate_due(&mut self, scheduler: &Scheduler) {
+        let now = Instant::now();
+        for (index, trigger) in self.triggers.iter_mut() {
+            let due = trigger.due(now);
+            let oversized = trigger.oversized(due);
+            if oversized.is_empty() || SHUTDOWN.load(Ordering::SeqCst) {
+                continue;
+            }
+            let target = &scheduler.entries[*index].target;
+            log(&format!("[{}] Rotating {} files past max size {} bytes", target.name, oversized.len(), trigger.max_size));
+            for file in &oversized {
+                trigger.rotated(file, now);
+            }
+            match rotate_oversized(target, &oversized) {
+                Ok(report) => {
+                    for record in report {
+                        log_record(target, &record);
+                    }
+                }
+                Err(error) => log_error(&format!("[{}] Size triggered run failed: {:#}", target.name, error)),
+            }
+        }
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.triggers.iter().filter_map(|(_, trigger)| trigger.next_due()).min()
+    }
+}
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    if signal == libc::SIGHUP {
+        RELOAD.store(true, Ordering::SeqCst);
+    }
+    else {
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    }
+}
+
+fn install_signal_handlers() -> anyhow::Result<()> {
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        // SAFETY: the handler only stores to atomics, which is async-signal-safe
+        let previous = unsafe { libc::signal(signal, handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+        if previous == libc::SIG_ERR {
+            return Err(anyhow::anyhow!("Unable to install handler for signal {}: {}", signal, std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// The journal, or stderr outside of systemd
+fn journal() -> &'static Journal {
+    static JOURNAL: OnceLock<Journal> = OnceLock::new();
+    JOURNAL.get_or_init(Journal::open)
+}
+
+fn log(message: &str) {
+    journal().send(PRIORITY_INFO, message, &[]);
+}
+
+fn log_error(message: &str) {
+    journal().send(PRIORITY_ERR, message, &[]);
+}
+
+/// Log an action record with its structured fields and target
+fn log_record(target: &Target, record: &ActionRecord) {
+    journal().record(&format!("[{}] ", target.name), record, &[("TARGET", target.name.clone())]);
+}
+
+/// Tell the service manager about a state change - a failing notification socket is logged, not fatal
+fn notify_service(state: &str) {
+    if let Err(error) = notify(state) {
+        log_error(&format!("Unable to notify the service manager ({}): {}", state.replace('\n', " "), error));
+    }
+}
+
+/// Watchdog keep-alive, sent at most once per interval
+struct Watchdog {
+    interval: Option<Duration>,
+    last_ping: Instant,
+}
+
+impl Watchdog {
+    fn ping_if_due(&mut self) {
+        if self.interval.is_some_and(|interval| self.last_ping.elapsed() >= interval) {
+            notify_service("WATCHDOG=1");
+            self.last_ping = Instant::now();
+        }
+    }
+}
+
+/// CLOCK_MONOTONIC in microseconds, which `RELOADING=1` has to carry
+fn monotonic_usec() -> u64 {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: time is a valid timespec to write to
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    time.tv_sec as u64 * 1_000_000 + time.tv_nsec as u64 / 1_000
+}
+
+/// Load a policy and run its targets on their schedules until SIGTERM / SIGINT
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn run_daemon(policy_path: &Path) -> anyhow::Result<()> {
+    install_signal_handlers()?;
+    let with_shutdown = |mut targets: Vec<Target>| {
+        for target in targets.iter_mut() {
+            target.options.shutdown = Some(&SHUTDOWN);
+        }
+        targets
+    };
+
+    let mut scheduler = Scheduler::new(with_shutdown(load_policy_connected(policy_path)?), Local::now());
+    log(&format!("Loaded {} targets from '{}'", scheduler.entries.len(), policy_path.display()));
+    for entry in &scheduler.entries {
+        log(&format!("[{}] Next run at {}", entry.target.name, entry.next_run.to_rfc3339()));
+    }
+    let mut size_watch = SizeWatch::new(&scheduler)?;
+    let mut watchdog = Watchdog { interval: watchdog_interval(), last_ping: Instant::now() };
+    notify_service(&format!("READY=1\nSTATUS=Running {} targets", scheduler.entries.len()));
+
+    while !SHUTDOWN.load(Ordering::SeqCst) {
+        watchdog.ping_if_due();
+        if RELOAD.swap(false, Ordering::SeqCst) {
+            notify_service(&format!("RELOADING=1\nMONOTONIC_USEC={}", monotonic_usec()));
+            match load_policy_connected(policy_path) {
+                Ok(targets) => {
+                    scheduler.reload(with_shutdown(targets), Local::now());
+                    log(&format!("Reloaded {} targets from '{}'", scheduler.entries.len(), policy_path.display()));
+                    size_watch = SizeWatch::new(&scheduler).unwrap_or_else(|error| {
+                        log_error(&format!("Size watches disabled - {:#}", error));
+                        None
+                    });
+                }
+                Err(error) => log_error(&format!("Keeping the current policy - reload failed: {:#}", error)),
+            }
+            notify_service(&format!("READY=1\nSTATUS=Running {} targets", scheduler.entries.len()));
+        }
+
+        for index in scheduler.due(Local::now()) {
+            if SHUTDOWN.load(Ordering::SeqCst) {
+                break;
+            }
+            watchdog.ping_if_due();
+            let target = scheduler.entries[index].target.clone();
+            log(&format!("[{}] Rotating {}", target.name, target.directory));
+            match run_target(&target) {
+                Ok(report) => {
+                    for record in report {
+                        log_record(&target, &record);
+                    }
+                }
+                Err(error) => log_error(&format!("[{}] Run failed: {:#}", target.name, error)),
+            }
+            scheduler.mark_run(index, Local::now());
+        }
+
+        let until_next = scheduler
+            .next_wakeup()
+            .and_then(|next_run| (next_run - Local::now()).to_std().ok())
+            .unwrap_or(POLL_INTERVAL)
+            .min(POLL_INTERVAL);
+        match size_watch.as_mut() {
+            Some(watch) => {
+                let timeout = watch
+                    .next_due()
+                    .map(|next_due| next_due.saturating_duration_since(Instant::now()))
+                    .map_or(until_next, |until_due| until_due.min(until_next));
+                let handled = watch
+                    .watcher
+                    .wait(timeout)
+                    .and_then(|events| watch.handle_events(&scheduler, events));
+                if let Err(error) = handled {
+                    log_error(&format!("Size watch failed: {:#}", error));
+                    thread::sleep(timeout);
+                }
+                watch.rotate_due(&scheduler);
+            }
+            None => thread::sleep(until_next),
+        }
+    }
+
+    notify_service("STOPPING=1");
+    log("Shutting down");
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! Why a file gets its action
+//!
+//! Every rule of a rule set is checked against the file and each condition is kept with the values it was checked
+//! against, so `explain` can show e.g. that a file exactly one day old with `--keep-days 1` fails `age < keep-days`
+//! of the classic preset. `RuleSet::decide` goes through the same check, so the explanation cannot drift from what a
+//! run does
+
+use std::fmt;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+
+use crate::Action;
+
+/// One condition of a rule with the values it was checked against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionCheck {
+    pub condition: String,
+    pub actual: String,
+    pub passed: bool,
+}
+
+/// A rule and how each of its conditions came out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleCheck {
+    pub name: String,
+    pub action: Action,
+    pub conditions: Vec<ConditionCheck>,
+}
+
+impl RuleCheck {
+    pub fn matched(&self) -> bool {
+        self.conditions.iter().all(|check| check.passed)
+    }
+}
+
+/// Every rule checked against a file, and the outcome
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub file: PathBuf,
+    /// Preset or rules file the rules came from
+    pub rule_set: String,
+    pub extension: String,
+    pub modified: DateTime<Local>,
+    /// Whole days since the last modification, rounded down
+    pub age_days: i64,
+    pub age_hours: i64,
+    pub threshold_days: i64,
+    pub rules: Vec<RuleCheck>,
+    pub action: Action,
+    /// Rule that decided the action - None when no rule matched
+    pub decided_by: Option<String>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "File: {} | Rules: {} | File Extension: {} | Modified: {} | Age: {} days ({} hours) | Keep Days: {}",
+                 self.file.display(),
+                 self.rule_set,
+                 self.extension,
+                 self.modified.format("%Y-%m-%d %H:%M:%S"),
+                 self.age_days,
+                 self.age_hours,
+                 self.threshold_days,
+        )?;
+        for rule in &self.rules {
+            writeln!(f, "Rule: {} | Action Type: {} | Matched: {}", rule.name, rule.action, if rule.matched() { "yes" } else { "no" })?;
+            for check in &rule.conditions {
+                writeln!(f, "    [{}] {}: {}", if check.passed { "pass" } else { "FAIL" }, check.condition, check.actual)?;
+            }
+        }
+        match &self.decided_by {
+            Some(rule) => write!(f, "Result: {} | Decided By: {}", self.action, rule),
+            None => write!(f, "Result: {} | Decided By: no rule matched", self.action),
+        }
+    }
+}
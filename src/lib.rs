@@ -1,16 +1,47 @@
 // old - use strum_macros::Display;
 
+pub mod bundle;
+pub mod catalog;
+pub mod crypto;
+pub mod daemon;
+pub mod explain;
+pub mod manifest;
+pub mod parallel;
+pub mod policy;
+pub mod redact;
+pub mod restore;
+pub mod rules;
+pub mod schedule;
+pub mod search;
+pub mod state;
+pub mod syslog;
+pub mod systemd;
+pub mod watch;
+
+use bundle::BundleOptions;
+use crypto::ArchiveSink;
+use parallel::{GzipWriter, ParallelCompression};
+use redact::{RedactingReader, RedactionCounts, Redactor};
+use syslog::SyslogSender;
+
 use std::fs;
 use std::io;
+use std::io::Read;
+use std::collections::BTreeMap;
+use std::time::SystemTime;
 use std::fmt;
 use std::path;
 use std::str::FromStr;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
 use tar::Builder;
-use flate2::Compression;
-use flate2::write::GzEncoder;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Datelike, Local, NaiveDate, Timelike, Utc};
 use clap::ValueEnum;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 
 
 /// only allow explicit values and assign an extension type for each
@@ -20,6 +51,12 @@ pub enum ArchiveType {
     Tar,
     TarGunzip,
     Zip,
+    /// Single file gzip stream, no tar wrapper
+    Gzip,
+    /// Single file zstd stream, no tar wrapper
+    Zstd,
+    /// Single file xz stream, no tar wrapper
+    Xz,
 }
 
 impl ArchiveType {
@@ -28,11 +65,42 @@ impl ArchiveType {
             ArchiveType::Tar => "tar",
             ArchiveType::TarGunzip => "tar.gz",
             ArchiveType::Zip => "zip",
+            ArchiveType::Gzip => "gz",
+            ArchiveType::Zstd => "zst",
+            ArchiveType::Xz => "xz",
+        }
+    }
+
+    /// Whether the archive holds a single compressed stream rather than named entries
+    pub fn is_single_file(&self) -> bool {
+        matches!(self, ArchiveType::Gzip | ArchiveType::Zstd | ArchiveType::Xz)
+    }
+
+    /// Work out the archive type of an existing archive from its name
+    pub fn from_archive_path(file_path: &str) -> Option<ArchiveType> {
+        match file_path {
+            _ if file_path.ends_with(".tar.gz") => Some(ArchiveType::TarGunzip),
+            _ if file_path.ends_with(".tar") => Some(ArchiveType::Tar),
+            _ if file_path.ends_with(".zip") => Some(ArchiveType::Zip),
+            _ if file_path.ends_with(".gz") => Some(ArchiveType::Gzip),
+            _ if file_path.ends_with(".zst") => Some(ArchiveType::Zstd),
+            _ if file_path.ends_with(".xz") => Some(ArchiveType::Xz),
+            _ => None,
         }
     }
 }
 
 
+/// How per-file archives are named
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
+pub enum NamingScheme {
+    /// `app.log_2025_09_26.tar.gz`
+    #[default]
+    Date,
+    /// `app.log.1.tar.gz`, shifted to `.2`, `.3`, ... on every rotation
+    Numbered,
+}
+
 /// This incorporates some of the archive types along with several other extensions for possible log files
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FileType {
@@ -48,6 +116,9 @@ pub enum FileType {
     Gz,
     Tar,
     Zip,
+    Zst,
+    Xz,
+    Age,
     Unknown,
 }
 
@@ -66,6 +137,9 @@ impl fmt::Display for FileType {
             FileType::Gz => "gz",
             FileType::Tar => "tar",
             FileType::Zip => "zip",
+            FileType::Zst => "zst",
+            FileType::Xz => "xz",
+            FileType::Age => "age",
             FileType::Unknown => "unknown",
         };
         write!(f, "{}", s)
@@ -88,6 +162,9 @@ impl FromStr for FileType {
             "gz" => Ok(FileType::Gz),
             "tar" => Ok(FileType::Tar),
             "zip" => Ok(FileType::Zip),
+            "zst" | "zstd" => Ok(FileType::Zst),
+            "xz" => Ok(FileType::Xz),
+            "age" => Ok(FileType::Age),
             _ => Ok(FileType::Unknown),
         }
     }
@@ -96,7 +173,7 @@ impl FromStr for FileType {
 
 /// Self-explanatory
 pub fn get_file_mtime_diff(file: &str) -> anyhow::Result<i64> {
-    let _file_metadata: DateTime <Utc> = fs::metadata(file.to_string())?
+    let _file_metadata: DateTime <Utc> = fs::metadata(file)?
         .modified()?
         .into();
 
@@ -108,55 +185,251 @@ pub fn get_file_mtime_diff(file: &str) -> anyhow::Result<i64> {
     Ok(diff)
 }
 
+/// Extensions treated as archives - the `archive` file type of the rules
+pub const ARCHIVE_EXTENSIONS: [&str; 6] = ["gz", "tar", "zip", "zst", "xz", "age"];
+
 /// meat and potatoes - consumes file and steps through bucketing logic (remove, archive, truncate)
+/// Decided by the classic rules preset - see `rules`
 pub fn archive_remove_truncate_file_bucketing(file: &str, threshold_days: i64) -> anyhow::Result<i32> {
-    let _mtime_diff = get_file_mtime_diff(file)?;
-    let _file_extension = get_file_extension(file);
-
-    let check_if_archive_file = match _file_extension.as_str() {
-        "gz" => true,
-        "tar" => true,
-        "zip" => true,
-        _ => false,
+    match bucket_file(Path::new(file), threshold_days)?.action {
+        Action::Archive => Ok(0),
+        Action::Remove => Ok(1),
+        Action::Truncate => Ok(2),
+        _ => Ok(3), // Unchanged
+    }
+}
+
+/// Check the default rules against a file, keeping every condition with the values it was checked against
+pub fn bucket_file(file: &Path, threshold_days: i64) -> anyhow::Result<explain::Explanation> {
+    rules::default_rules().explain(file, threshold_days)
+}
+
+/// Archive a provided file with the selected backend, then truncate the source
+/// The source is only truncated once the archive has been written and renamed into place - a failed backend
+/// leaves it untouched. With `options.verify` the archive is also re-read and compared against a snapshot of
+/// the source first. Returns the path of the new archive
+pub fn archive_selection_and_process(file_path: &str, archive_type: ArchiveType, options: &RunOptions) -> anyhow::Result<String> {
+    archive_file(file_path, archive_type, options).map(|outcome| outcome.archive)
+}
+
+/// What `archive_file` produced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveOutcome {
+    pub archive: String,
+    /// Redactions per rule, empty when redaction is off
+    pub redactions: RedactionCounts,
+}
+
+/// `archive_selection_and_process`, also handing back the redaction counts for the report
+pub fn archive_file(file_path: &str, archive_type: ArchiveType, options: &RunOptions) -> anyhow::Result<ArchiveOutcome> {
+    if options.verify && !options.recipients.is_empty() {
+        return Err(anyhow::anyhow!("Encrypted archives cannot be verified without the private key"));
+    }
+    if options.append && !options.recipients.is_empty() {
+        return Err(anyhow::anyhow!("Encrypted archives cannot be appended to without the private key"));
+    }
+    if options.append && archive_type.is_single_file() {
+        return Err(anyhow::anyhow!("Append mode needs a tar, tar.gz or zip archive method"));
+    }
+    if options.append && options.naming == NamingScheme::Numbered {
+        return Err(anyhow::anyhow!("Append mode needs the date naming scheme"));
+    }
+    if options.delay_compress {
+        return rotate_delayed(file_path, archive_type, options);
+    }
+    if options.naming == NamingScheme::Numbered {
+        shift_numbered_archives(file_path, &archive_type, options)?;
+    }
+
+    let new_file_path = archive_path_with_options(file_path, &archive_type, options);
+    let entry_name = archive_entry_name(file_path, &archive_type, options)?;
+    let source_snapshot = if options.verify || options.manifest { Some(file_digest(Path::new(file_path))?) } else { None };
+    let content_stats = catalog::scan_file(Path::new(file_path))?;
+    // With redaction on, the archive holds the redacted form of the source
+    let expected_entry = match (&options.redactor, options.verify) {
+        (Some(redactor), true) => Some(redacted_digest(Path::new(file_path), redactor)?.1),
+        (None, true) => source_snapshot.clone(),
+        (_, false) => None,
     };
 
-    let check_if_unknown_file = match _file_extension.as_str() {
-        "unknown" => true,
-        _ => false,
+    let result = match archive_type {
+        ArchiveType::Tar => tar_file_with_options(file_path, archive_type, options),
+        ArchiveType::TarGunzip => tar_gunzip_file_with_options(file_path, archive_type, options),
+        ArchiveType::Zip => zip_file_with_options(file_path, archive_type, options),
+        ArchiveType::Gzip | ArchiveType::Zstd | ArchiveType::Xz => compress_file_with_options(file_path, archive_type, options),
     };
 
-    match _mtime_diff {
-        _ if (_mtime_diff > threshold_days)
-            && check_if_archive_file
-            && !check_if_unknown_file => Ok(1), // Remove
-        _ if (_mtime_diff < threshold_days)
-            && (_mtime_diff <= 1)
-            && !check_if_archive_file
-            && !check_if_unknown_file => Ok(0), // Archive
-        _ if (_mtime_diff <= threshold_days)
-            && (_mtime_diff > 1)
-            && !check_if_archive_file
-            && !check_if_unknown_file => Ok(2), // Truncate
-        _ => Ok(3) // Unchanged
+    let redactions = match result {
+        Ok(redactions) => redactions,
+        Err(error) => return Err(error.context(format!("Failed to archive '{}'", file_path))),
+    };
+
+    apply_archive_permissions(Path::new(&new_file_path), Path::new(file_path), options.archive_mode)?;
+
+    if let Some((entry_length, entry_sha256)) = &expected_entry {
+        let verified = if options.append {
+            verify_archive_entry(Path::new(&new_file_path), &entry_name, *entry_length, entry_sha256)
+        }
+        else {
+            verify_archive_against_source(Path::new(&new_file_path), *entry_length, entry_sha256)
+        };
+        if let Err(error) = verified {
+            // Do not leave an archive behind that retention would treat as a good copy - unless it also holds
+            // entries from earlier runs
+            if !options.append {
+                fs::remove_file(&new_file_path).ok();
+            }
+            return Err(error.context(format!("Verification of '{}' failed, source left untouched", new_file_path)));
+        }
+    }
+
+    if let Some((_, source_sha256)) = &source_snapshot
+        && options.manifest
+    {
+        manifest::record_archive(Path::new(&new_file_path), source_sha256)
+            .map_err(|error| error.context(format!("Failed to record '{}' in the manifest, source left untouched", new_file_path)))?;
+    }
+    catalog::record_archive(Path::new(&new_file_path), &[path::PathBuf::from(file_path)], &content_stats, options.append)
+        .map_err(|error| error.context(format!("Failed to record '{}' in the catalog, source left untouched", new_file_path)))?;
+
+    truncate_file(file_path)
+        .map_err(|error| anyhow::anyhow!("Archived to '{}' but failed to truncate '{}': {}", new_file_path, file_path, error))?;
+    if let Some(create_mode) = options.create_mode {
+        fs::set_permissions(file_path, fs::Permissions::from_mode(create_mode))?;
     }
+    Ok(ArchiveOutcome { archive: new_file_path, redactions })
 }
 
-///
-pub fn archive_selection_and_process(file_path: &str, archive_type: ArchiveType) {
-    match archive_type {
-        ArchiveType::Tar => {
-            tar_file(file_path, archive_type).ok();
-            truncate_file(file_path);
+/// Destination path of the archive created for a provided file, e.g. `app.log` -> `app.log_2025_09_26.tar.gz`
+pub fn archive_path_for(file_path: &str, archive_type: &ArchiveType) -> String {
+    file_path.to_string() + "_" + &get_date() + "." + archive_type.as_str()
+}
+
+/// Destination path of the archive created for a provided file under `options` - encrypted archives get `.age`
+pub fn archive_path_with_options(file_path: &str, archive_type: &ArchiveType, options: &RunOptions) -> String {
+    let archive_path = match options.naming {
+        NamingScheme::Date => archive_path_for(file_path, archive_type),
+        NamingScheme::Numbered => return numbered_archive_path(file_path, 1, archive_type, options),
+    };
+    if options.recipients.is_empty() { archive_path } else { archive_path + crypto::ENCRYPTED_SUFFIX }
+}
+
+/// Path of a provided file's archive number `number` in the numbered scheme, e.g. `app.log.2.tar.gz`
+pub fn numbered_archive_path(file_path: &str, number: u32, archive_type: &ArchiveType, options: &RunOptions) -> String {
+    let archive_path = format!("{}.{}.{}", file_path, number, archive_type.as_str());
+    if options.recipients.is_empty() { archive_path } else { archive_path + crypto::ENCRYPTED_SUFFIX }
+}
+
+/// Numbered archives of a provided file with the extension of `archive_type`, highest number first
+pub fn numbered_archives(file_path: &str, archive_type: &ArchiveType, options: &RunOptions) -> anyhow::Result<Vec<(u32, path::PathBuf)>> {
+    let source_path = Path::new(file_path);
+    let directory = source_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let prefix = format!("{}.", source_path.file_name().unwrap_or_default().to_string_lossy());
+    let suffix = format!(".{}{}", archive_type.as_str(), if options.recipients.is_empty() { "" } else { crypto::ENCRYPTED_SUFFIX });
+
+    let mut archives: Vec<(u32, path::PathBuf)> = gather_files_from_directory(&directory.to_string_lossy())?
+        .into_iter()
+        .filter_map(|file| {
+            let name = file.file_name()?.to_str()?;
+            let number = name.strip_prefix(&prefix)?.strip_suffix(&suffix)?.parse().ok()?;
+            Some((number, file))
+        })
+        .collect();
+    archives.sort_by_key(|(number, _)| std::cmp::Reverse(*number));
+    Ok(archives)
+}
+
+/// Make room for a new `.1` archive - every numbered archive of a provided file moves up by one, highest first
+pub fn shift_numbered_archives(file_path: &str, archive_type: &ArchiveType, options: &RunOptions) -> anyhow::Result<()> {
+    for (number, archive) in numbered_archives(file_path, archive_type, options)? {
+        let shifted = path::PathBuf::from(numbered_archive_path(file_path, number + 1, archive_type, options));
+        fs::rename(&archive, &shifted)?;
+        if options.manifest {
+            manifest::record_rename(&archive, &shifted)?;
         }
-        ArchiveType::TarGunzip => {
-            tar_gunzip_file(file_path, archive_type).ok();
-            truncate_file(file_path);
+        catalog::record_rename(&archive, &shifted)?;
+    }
+    Ok(())
+}
+
+/// Plain text copy of the previous rotation kept by delayed compression, e.g. `app.log.1`
+pub fn delayed_rotation_path(file_path: &str) -> path::PathBuf {
+    path::PathBuf::from(format!("{}.1", file_path))
+}
+
+/// delaycompress - compress the previous run's plain `app.log.1` into `app.log.2.<ext>`, then copy the live file
+/// into a fresh plain `app.log.1` and truncate it. Returns the plain copy as the archive
+fn rotate_delayed(file_path: &str, archive_type: ArchiveType, options: &RunOptions) -> anyhow::Result<ArchiveOutcome> {
+    if options.naming != NamingScheme::Numbered {
+        return Err(anyhow::anyhow!("Delayed compression needs the numbered naming scheme"));
+    }
+    if !options.recipients.is_empty() {
+        return Err(anyhow::anyhow!("Delayed compression keeps the last rotation in plain text and cannot be combined with encryption"));
+    }
+
+    let source_path = Path::new(file_path);
+    let previous = delayed_rotation_path(file_path);
+    let source_snapshot = if options.verify { Some(file_digest(source_path)?) } else { None };
+    // The copy takes exactly the bytes the expected digest covers
+    let (copy_length, expected_copy) = match (&options.redactor, source_snapshot) {
+        (Some(redactor), Some(_)) => {
+            let (length, digest) = redacted_digest(source_path, redactor)?;
+            (Some(length), Some(digest))
         }
-        ArchiveType::Zip => {
-            zip_file(file_path, archive_type).ok();
-            truncate_file(file_path);
+        (_, Some(snapshot)) => (Some(snapshot.0), Some(snapshot)),
+        (_, None) => (None, None),
+    };
+
+    shift_numbered_archives(file_path, &archive_type, options)?;
+    if previous.exists() {
+        let compressed = numbered_archive_path(file_path, 2, &archive_type, options);
+        let previous_snapshot = if options.verify || options.manifest { Some(file_digest(&previous)?) } else { None };
+        let previous_stats = catalog::scan_file(&previous)?;
+        // The plain copy went through the redactor when it was written
+        let compress_options = RunOptions { redactor: None, ..options.clone() };
+        write_archive(&previous, Path::new(&compressed), &archive_entry_name(file_path, &archive_type, options)?, &archive_type, &compress_options)
+            .map_err(|error| error.context(format!("Failed to compress '{}'", previous.display())))?;
+        apply_archive_permissions(Path::new(&compressed), &previous, options.archive_mode)?;
+
+        if let Some((previous_length, previous_sha256)) = &previous_snapshot {
+            if options.verify
+                && let Err(error) = verify_archive_against_source(Path::new(&compressed), *previous_length, previous_sha256)
+            {
+                fs::remove_file(&compressed).ok();
+                return Err(error.context(format!("Verification of '{}' failed, '{}' left untouched", compressed, previous.display())));
+            }
+            if options.manifest {
+                manifest::record_archive(Path::new(&compressed), previous_sha256)?;
+            }
         }
+        catalog::record_archive(Path::new(&compressed), &[source_path.to_path_buf()], &previous_stats, false)?;
+        fs::remove_file(&previous)?;
+    }
+
+    let metadata = fs::metadata(source_path)?;
+    let mut redactions = RedactionCounts::new();
+    write_atomically(&previous, |mut previous_file| {
+        redactions = copy_source(source_path, copy_length.unwrap_or(metadata.len()), &mut previous_file, options)?;
+        previous_file.set_modified(metadata.modified()?)?;
+        Ok(previous_file)
+    })?;
+    apply_archive_permissions(&previous, source_path, options.archive_mode)?;
+
+    if let Some((copy_length, copy_sha256)) = &expected_copy
+        && file_digest(&previous)? != (*copy_length, copy_sha256.clone())
+    {
+        return Err(anyhow::anyhow!("Verification of '{}' failed, source left untouched", previous.display()));
     }
+
+    truncate_file(file_path)
+        .map_err(|error| anyhow::anyhow!("Copied to '{}' but failed to truncate '{}': {}", previous.display(), file_path, error))?;
+    if let Some(create_mode) = options.create_mode {
+        fs::set_permissions(file_path, fs::Permissions::from_mode(create_mode))?;
+    }
+    Ok(ArchiveOutcome { archive: previous.to_string_lossy().to_string(), redactions })
 }
 
 pub fn get_date() -> String {
@@ -164,9 +437,16 @@ pub fn get_date() -> String {
     now.format("%Y_%m_%d").to_string()
 }
 
-/// Create a vector to store all *unfiltered* files in the provided directory
+/// Create a vector to store all files in the provided directory, leaving out the tool's own files (see `is_internal_file`)
 pub fn gather_files_from_directory(dir_path: &str) -> anyhow::Result<Vec<path::PathBuf>> {
-    let files: Vec<path::PathBuf> = fs::read_dir(dir_path.to_string())?
+    let mut files = directory_files(dir_path)?;
+    files.retain(|file| !is_internal_file(file));
+    Ok(files)
+}
+
+/// Every file in the provided directory, the tool's own files included
+fn directory_files(dir_path: &str) -> anyhow::Result<Vec<path::PathBuf>> {
+    let files: Vec<path::PathBuf> = fs::read_dir(dir_path)?
         .filter_map(|entry| {
             let entry = entry.ok()?;
             let path = entry.path();
@@ -180,64 +460,591 @@ pub fn gather_files_from_directory(dir_path: &str) -> anyhow::Result<Vec<path::P
     Ok(files)
 }
 
-/// Truncate a provided file
-pub fn truncate_file(file_path: &str) {
-    let file = fs::File::create(file_path.to_string()).unwrap();
-    file.set_len(0).unwrap();
+/// Whether a file is the tool's own bookkeeping - the manifest, catalog and state file, or an archive temp file
+/// No rule is ever checked against these
+pub fn is_internal_file(file: &Path) -> bool {
+    let Some(name) = file.file_name().and_then(|name| name.to_str()) else { return false };
+    [manifest::MANIFEST_FILE_NAME, catalog::CATALOG_FILE_NAME, state::STATE_FILE_NAME].contains(&name)
+        || (name.starts_with('.') && name.ends_with(TEMP_ARCHIVE_SUFFIX))
+}
+
+/// `gather_files_from_directory`, descending into every subdirectory
+pub fn gather_files_recursively(dir_path: &str) -> anyhow::Result<Vec<path::PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![path::PathBuf::from(dir_path)];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            }
+            else if path.is_file() && !is_internal_file(&path) {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Truncate a provided file - a file that is gone is not created again
+pub fn truncate_file<P: AsRef<Path>>(file_path: P) -> io::Result<()> {
+    fs::OpenOptions::new().write(true).open(file_path)?.set_len(0)
 }
 
 /// Create a tarball of a provided file and compress
 pub fn tar_gunzip_file(file_path: &str, archive_type: ArchiveType) -> anyhow::Result<()> {
+    tar_gunzip_file_with_options(file_path, archive_type, &RunOptions::default()).map(|_| ())
+}
+
+/// Create a compressed tarball of a provided file, encrypting it when `options` carries recipients
+/// and redacting it when `options` carries a redactor. Returns the redaction counts
+pub fn tar_gunzip_file_with_options(file_path: &str, archive_type: ArchiveType, options: &RunOptions) -> anyhow::Result<RedactionCounts> {
     if archive_type == ArchiveType::TarGunzip {
-        let old_file = Path::new(file_path).file_name().unwrap().to_str().unwrap();
-        let new_file_path = file_path.to_string() + "_" + &get_date() + "." +archive_type.as_str();
-        let tar_gz_file = fs::File::create(new_file_path.clone())?;
-
-        let encoder = GzEncoder::new(tar_gz_file, Compression::default());
-        let mut tar_builder = Builder::new(encoder);
-        
-        tar_builder.append_path_with_name(file_path.to_string(), old_file.to_string())?;
-        tar_builder.finish()?;
-        Ok(())
+        let entry_name = archive_entry_name(file_path, &archive_type, options)?;
+        let new_file_path = archive_path_with_options(file_path, &archive_type, options);
+        write_archive(Path::new(file_path), Path::new(&new_file_path), &entry_name, &archive_type, options)
     }
     else { Err(anyhow::anyhow!("Archive Type for 'TarGunzip' did not match expected type"))? }
 }
 
 /// Create a non-compressed tarball of a provided file
 pub fn tar_file(file_path: &str, archive_type: ArchiveType) -> anyhow::Result<()> {
-    if archive_type == ArchiveType::Tar {
-        let old_file = Path::new(file_path).file_name().unwrap().to_str().unwrap();
-        let new_file_path = file_path.to_string() + "_" + &get_date() + "." +archive_type.as_str();
-        let tar_file = fs::File::create(new_file_path.clone())?;
-
-        let mut tar_builder = Builder::new(tar_file);
-
-        tar_builder.append_path_with_name(file_path.to_string(), old_file.to_string())?;
-        tar_builder.finish()?;
+    tar_file_with_options(file_path, archive_type, &RunOptions::default()).map(|_| ())
+}
 
-        Ok(())
+/// Create a non-compressed tarball of a provided file, encrypting it when `options` carries recipients
+/// and redacting it when `options` carries a redactor. Returns the redaction counts
+pub fn tar_file_with_options(file_path: &str, archive_type: ArchiveType, options: &RunOptions) -> anyhow::Result<RedactionCounts> {
+    if archive_type == ArchiveType::Tar {
+        let entry_name = archive_entry_name(file_path, &archive_type, options)?;
+        let new_file_path = archive_path_with_options(file_path, &archive_type, options);
+        write_archive(Path::new(file_path), Path::new(&new_file_path), &entry_name, &archive_type, options)
     }
     else { Err(anyhow::anyhow!("Archive Type for 'Tar' did not match expected type"))? }
 }
 
 /// Create a zip archive of a provided file
 pub fn zip_file(file_path: &str, archive_type: ArchiveType) -> anyhow::Result<()> {
+    zip_file_with_options(file_path, archive_type, &RunOptions::default()).map(|_| ())
+}
+
+/// Create a zip archive of a provided file, encrypting it when `options` carries recipients
+/// and redacting it when `options` carries a redactor. Returns the redaction counts
+pub fn zip_file_with_options(file_path: &str, archive_type: ArchiveType, options: &RunOptions) -> anyhow::Result<RedactionCounts> {
     if archive_type == ArchiveType::Zip {
-        let new_file_path = file_path.to_string() + "_" + &get_date() + "." +archive_type.as_str();
-        let zip_file = fs::File::create(new_file_path.clone())?;
+        let entry_name = archive_entry_name(file_path, &archive_type, options)?;
+        let new_file_path = archive_path_with_options(file_path, &archive_type, options);
+        write_archive(Path::new(file_path), Path::new(&new_file_path), &entry_name, &archive_type, options)
+    }
+    else { Err(anyhow::anyhow!("Archive Type for 'Zip' did not match expected type"))? }
+}
 
-        let mut zip_builder = zip::ZipWriter::new(zip_file);
-        let options: zip::write::FileOptions<'_, ()> = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+/// Compress a provided file on its own, without a tar wrapper - `app.log` -> `app.log_2025_09_26.gz` / `.zst` / `.xz`
+pub fn compress_file(file_path: &str, archive_type: ArchiveType) -> anyhow::Result<()> {
+    compress_file_with_options(file_path, archive_type, &RunOptions::default()).map(|_| ())
+}
 
+/// Stream-compress a provided file with gzip, zstd or xz, encrypting it when `options` carries recipients
+/// and redacting it when `options` carries a redactor. Returns the redaction counts
+pub fn compress_file_with_options(file_path: &str, archive_type: ArchiveType, options: &RunOptions) -> anyhow::Result<RedactionCounts> {
+    if archive_type.is_single_file() {
+        let entry_name = archive_entry_name(file_path, &archive_type, options)?;
+        let new_file_path = archive_path_with_options(file_path, &archive_type, options);
+        write_archive(Path::new(file_path), Path::new(&new_file_path), &entry_name, &archive_type, options)
+    }
+    else { Err(anyhow::anyhow!("Archive Type for 'Gzip', 'Zstd' or 'Xz' did not match expected type"))? }
+}
 
-        let mut source_file = fs::File::open(file_path.to_string())?;
-        zip_builder.start_file(file_path.to_string(), options)?;
+/// Write a provided file into `archive_path` as `entry_name` with any backend - the archive is written atomically
+/// An age stream cannot seek, so encrypted zips are written in streaming mode (data descriptors after each entry).
+/// The gzip header of single file streams carries the entry name and the source mtime, so `gunzip -N` restores both
+pub fn write_archive(source_path: &Path, archive_path: &Path, entry_name: &str, archive_type: &ArchiveType, options: &RunOptions) -> anyhow::Result<RedactionCounts> {
+    let mut counts = RedactionCounts::new();
+    let metadata = fs::metadata(source_path)?;
+    let threads = options.parallel_compression.and_then(|parallel| parallel.threads_for(metadata.len()));
 
-        io::copy(&mut source_file, &mut zip_builder)?;
-        zip_builder.finish()?;
-        Ok(())
+    write_atomically(archive_path, |archive_file| {
+        let sink = ArchiveSink::new(archive_file, &options.recipients)?;
+        let sink = match archive_type {
+            ArchiveType::Tar => {
+                let mut tar_builder = Builder::new(sink);
+                if options.append {
+                    copy_existing_tar_entries(archive_path, archive_type, &mut tar_builder)?;
+                }
+                counts = append_tar_entry(&mut tar_builder, source_path, entry_name, options)?;
+                tar_builder.into_inner()?
+            }
+            ArchiveType::TarGunzip => {
+                let mut tar_builder = Builder::new(GzipWriter::new(sink, None, 0, threads));
+                if options.append {
+                    copy_existing_tar_entries(archive_path, archive_type, &mut tar_builder)?;
+                }
+                counts = append_tar_entry(&mut tar_builder, source_path, entry_name, options)?;
+                tar_builder.into_inner()?.finish()?
+            }
+            ArchiveType::Zip => match sink {
+                ArchiveSink::Plain(zip_file) => {
+                    let mut zip_builder = open_zip_writer(archive_path, zip_file, options)?;
+                    counts = append_zip_entry(&mut zip_builder, source_path, entry_name, options)?;
+                    return Ok(zip_builder.finish()?);
+                }
+                sink => {
+                    let mut zip_builder = zip::ZipWriter::new_stream(sink);
+                    counts = append_zip_entry(&mut zip_builder, source_path, entry_name, options)?;
+                    zip_builder.finish()?.into_inner()
+                }
+            },
+            ArchiveType::Gzip => {
+                let mtime = metadata
+                    .modified()?
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|since_epoch| since_epoch.as_secs() as u32)
+                    .unwrap_or(0);
+                let mut encoder = GzipWriter::new(sink, Some(entry_name.as_bytes()), mtime, threads);
+                counts = copy_source(source_path, metadata.len(), &mut encoder, options)?;
+                encoder.finish()?
+            }
+            ArchiveType::Zstd => {
+                let mut encoder = zstd::stream::write::Encoder::new(sink, zstd::DEFAULT_COMPRESSION_LEVEL)?;
+                if let Some(threads) = threads {
+                    encoder.multithread(threads as u32)?;
+                }
+                counts = copy_source(source_path, metadata.len(), &mut encoder, options)?;
+                encoder.finish()?
+            }
+            ArchiveType::Xz => {
+                let mut encoder = match threads {
+                    Some(threads) => {
+                        let stream = xz2::stream::MtStreamBuilder::new().threads(threads as u32).preset(6).encoder()?;
+                        xz2::write::XzEncoder::new_stream(sink, stream)
+                    }
+                    None => xz2::write::XzEncoder::new(sink, 6),
+                };
+                counts = copy_source(source_path, metadata.len(), &mut encoder, options)?;
+                encoder.finish()?
+            }
+        };
+        Ok(sink.finish()?)
+    })?;
+    Ok(counts)
+}
+
+/// Append a provided file to a tarball, through the redactor when `options` carries one
+pub(crate) fn append_tar_entry<W: io::Write>(tar_builder: &mut Builder<W>, file_path: &Path, entry_name: &str, options: &RunOptions) -> io::Result<RedactionCounts> {
+    match &options.redactor {
+        Some(redactor) => append_file_redacted(tar_builder, file_path, entry_name, redactor),
+        None => append_file_with_metadata(tar_builder, file_path, entry_name).map(|_| RedactionCounts::new()),
     }
-    else { Err(anyhow::anyhow!("Archive Type for 'Zip' did not match expected type"))? }
+}
+
+/// Stream a provided file into a zip entry, through the redactor when `options` carries one
+pub(crate) fn append_zip_entry<W: io::Write + io::Seek>(zip_builder: &mut zip::ZipWriter<W>, file_path: &Path, entry_name: &str, run_options: &RunOptions) -> anyhow::Result<RedactionCounts> {
+    let metadata = fs::metadata(file_path)?;
+    // Zip has no room for uid / gid or xattrs - mode and mtime are kept
+    let options: zip::write::FileOptions<'_, ()> = zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .unix_permissions(metadata.permissions().mode())
+        .last_modified_time(zip_datetime(metadata.modified()?));
+
+    zip_builder.start_file(entry_name, options)?;
+    Ok(copy_source(file_path, metadata.len(), zip_builder, run_options)?)
+}
+
+/// Stream the first `length` bytes of a provided file into `writer`, through the redactor when `options` carries one
+fn copy_source<W: io::Write>(file_path: &Path, length: u64, writer: &mut W, options: &RunOptions) -> io::Result<RedactionCounts> {
+    let source_file = fs::File::open(file_path)?;
+    match &options.redactor {
+        Some(redactor) => {
+            let mut reader = RedactingReader::new(io::BufReader::new(source_file.take(length)), redactor);
+            io::copy(&mut reader, writer)?;
+            Ok(reader.counts)
+        }
+        None => {
+            io::copy(&mut source_file.take(length), writer)?;
+            Ok(RedactionCounts::new())
+        }
+    }
+}
+
+/// Entry name a provided file gets in its archive - in append mode the first free one of `name`, `name.1`, `name.2`, ...
+/// Zip entries are named after the full path, tar entries (and single file streams) after the file
+pub fn archive_entry_name(file_path: &str, archive_type: &ArchiveType, options: &RunOptions) -> anyhow::Result<String> {
+    let name = match archive_type {
+        ArchiveType::Zip => file_path.to_string(),
+        _ => Path::new(file_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+    if !options.append {
+        return Ok(name);
+    }
+
+    let taken = existing_entry_names(Path::new(&archive_path_with_options(file_path, archive_type, options)))?;
+    Ok(unique_entry_name(&name, &taken))
+}
+
+/// Names of the entries already in an archive - empty when there is no archive yet
+pub fn existing_entry_names(archive_path: &Path) -> anyhow::Result<Vec<String>> {
+    if !archive_path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(archive_entry_digests(archive_path)?
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect())
+}
+
+/// First of `name`, `name.1`, `name.2`, ... not in `taken`
+pub fn unique_entry_name(name: &str, taken: &[String]) -> String {
+    let mut candidate = name.to_string();
+    let mut suffix = 1;
+    while taken.contains(&candidate) {
+        candidate = format!("{}.{}", name, suffix);
+        suffix += 1;
+    }
+    candidate
+}
+
+/// Copy every entry of an existing tarball into a new one untouched, extension headers (long names, xattrs) included
+/// Tar has no index and gzip no random access, so appending rewrites the archive into the atomic temp file
+pub(crate) fn copy_existing_tar_entries<W: io::Write>(archive_path: &Path, archive_type: &ArchiveType, tar_builder: &mut Builder<W>) -> anyhow::Result<()> {
+    if !archive_path.exists() {
+        return Ok(());
+    }
+
+    let archive_file = fs::File::open(archive_path)?;
+    let reader: Box<dyn io::Read> = match archive_type {
+        ArchiveType::TarGunzip => Box::new(flate2::read::MultiGzDecoder::new(archive_file)),
+        _ => Box::new(archive_file),
+    };
+    let mut tar_archive = tar::Archive::new(reader);
+    for entry in tar_archive.entries()?.raw(true) {
+        let entry = entry?;
+        let header = entry.header().clone();
+        tar_builder.append(&header, entry)?;
+    }
+    Ok(())
+}
+
+/// Zip writer on top of a fresh temp file - in append mode the existing archive is copied in first and extended
+/// through its central directory, so the original stays intact until the temp file is renamed over it
+pub(crate) fn open_zip_writer(archive_path: &Path, mut zip_file: fs::File, options: &RunOptions) -> anyhow::Result<zip::ZipWriter<fs::File>> {
+    if options.append && archive_path.exists() {
+        io::copy(&mut fs::File::open(archive_path)?, &mut zip_file)?;
+        return Ok(zip::ZipWriter::new_append(zip_file)?);
+    }
+    Ok(zip::ZipWriter::new(zip_file))
+}
+
+/// Append a file to a tarball keeping its uid, gid, mode and mtime (`HeaderMode::Complete`)
+/// Extended attributes, SELinux labels included, go in a preceding PAX header as `SCHILY.xattr.*` records
+pub fn append_file_with_metadata<W: io::Write>(tar_builder: &mut Builder<W>, file_path: &Path, entry_name: &str) -> io::Result<()> {
+    tar_builder.mode(tar::HeaderMode::Complete);
+    append_pax_xattrs(tar_builder, file_path, entry_name)?;
+    tar_builder.append_path_with_name(file_path, entry_name)
+}
+
+/// Append a file to a tarball through a redactor, keeping the same metadata as `append_file_with_metadata`
+/// The entry size has to be in the header up front, so the source is redacted twice: once to measure, once to write.
+/// Both passes stop at the length the source had when we started, so a file still being written to cannot drift
+pub fn append_file_redacted<W: io::Write>(tar_builder: &mut Builder<W>, file_path: &Path, entry_name: &str, redactor: &Redactor) -> io::Result<RedactionCounts> {
+    let metadata = fs::metadata(file_path)?;
+    let redacted_reader = || -> io::Result<RedactingReader<'_, io::BufReader<io::Take<fs::File>>>> {
+        let source_file = fs::File::open(file_path)?;
+        Ok(RedactingReader::new(io::BufReader::new(source_file.take(metadata.len())), redactor))
+    };
+
+    let redacted_length = io::copy(&mut redacted_reader()?, &mut io::sink())?;
+
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata_in_mode(&metadata, tar::HeaderMode::Complete);
+    header.set_size(redacted_length);
+
+    append_pax_xattrs(tar_builder, file_path, entry_name)?;
+    let mut reader = redacted_reader()?;
+    tar_builder.append_data(&mut header, entry_name, (&mut reader).take(redacted_length))?;
+    Ok(reader.counts)
+}
+
+/// Length and SHA-256 of a provided file as it will look once redacted, along with the source length it covers
+/// Only the bytes present when the file is opened are read, like the archive writers do, so a log growing meanwhile
+/// does not make the digest cover more than the archive holds
+pub fn redacted_digest(file_path: &Path, redactor: &Redactor) -> anyhow::Result<(u64, (u64, String))> {
+    let source_file = fs::File::open(file_path)?;
+    let length = source_file.metadata()?.len();
+    let digest = reader_digest(RedactingReader::new(io::BufReader::new(source_file.take(length)), redactor))?;
+    Ok((length, digest))
+}
+
+/// Extended attributes, SELinux labels included, go in a PAX header ahead of the entry as `SCHILY.xattr.*` records
+fn append_pax_xattrs<W: io::Write>(tar_builder: &mut Builder<W>, file_path: &Path, entry_name: &str) -> io::Result<()> {
+    let xattrs = read_xattrs(file_path);
+    if !xattrs.is_empty() {
+        let mut pax_data = Vec::new();
+        for (name, value) in &xattrs {
+            pax_data.extend(pax_record(&format!("SCHILY.xattr.{}", name), value));
+        }
+
+        let mut pax_header = tar::Header::new_ustar();
+        pax_header.set_entry_type(tar::EntryType::XHeader);
+        pax_header.set_path(format!("PaxHeaders/{}", entry_name))?;
+        pax_header.set_size(pax_data.len() as u64);
+        pax_header.set_mode(0o644);
+        pax_header.set_cksum();
+        tar_builder.append(&pax_header, pax_data.as_slice())?;
+    }
+    Ok(())
+}
+
+/// Extended attributes of a provided file - empty when the filesystem does not support them
+pub fn read_xattrs(file_path: &Path) -> Vec<(String, Vec<u8>)> {
+    let Ok(names) = xattr::list(file_path) else { return Vec::new() };
+    names
+        .filter_map(|name| {
+            let value = xattr::get(file_path, &name).ok()??;
+            Some((name.to_string_lossy().to_string(), value))
+        })
+        .collect()
+}
+
+/// A single PAX extended header record - `"<length> <key>=<value>\n"`, where length counts the whole record
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let body_length = key.len() + value.len() + 3; // ' ', '=' and '\n'
+    let mut length = body_length + 1;
+    while length != body_length + length.to_string().len() {
+        length = body_length + length.to_string().len();
+    }
+
+    let mut record = format!("{} {}=", length, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/// Zip timestamps are local time without a zone and cannot go before 1980
+fn zip_datetime(mtime: SystemTime) -> zip::DateTime {
+    let local: DateTime<Local> = mtime.into();
+    zip::DateTime::from_date_and_time(
+        local.year() as u16,
+        local.month() as u8,
+        local.day() as u8,
+        local.hour() as u8,
+        local.minute() as u8,
+        local.second() as u8,
+    ).unwrap_or_default()
+}
+
+/// Parse an octal file mode such as `640` or `0640`
+pub fn parse_mode(mode: &str) -> anyhow::Result<u32> {
+    let parsed = u32::from_str_radix(mode.trim(), 8)
+        .map_err(|_| anyhow::anyhow!("Invalid octal file mode '{}'", mode))?;
+    if parsed > 0o7777 {
+        return Err(anyhow::anyhow!("File mode '{}' is out of range", mode));
+    }
+    Ok(parsed)
+}
+
+/// Give a new archive its final permissions - the configured archive mode, otherwise the source file's mode -
+/// and, when running as root, the source file's owner
+pub fn apply_archive_permissions(archive_path: &Path, source_path: &Path, archive_mode: Option<u32>) -> anyhow::Result<()> {
+    let source_metadata = fs::metadata(source_path)?;
+    let mode = archive_mode.unwrap_or(source_metadata.permissions().mode() & 0o7777);
+    fs::set_permissions(archive_path, fs::Permissions::from_mode(mode))?;
+
+    // SAFETY: geteuid has no preconditions
+    if unsafe { libc::geteuid() } == 0 {
+        std::os::unix::fs::chown(archive_path, Some(source_metadata.uid()), Some(source_metadata.gid()))?;
+    }
+    Ok(())
+}
+
+/// Suffix of the hidden temp files archives are written to before being renamed into place
+pub const TEMP_ARCHIVE_SUFFIX: &str = ".logrotate-tmp";
+
+/// Hidden temp file next to a provided archive path, e.g. `logs/app.log_2025_09_26.zip` -> `logs/.app.log_2025_09_26.zip.logrotate-tmp`
+pub fn temp_path_for(final_path: &Path) -> path::PathBuf {
+    let file_name = final_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    final_path.with_file_name(format!(".{}{}", file_name, TEMP_ARCHIVE_SUFFIX))
+}
+
+/// Write a file atomically - `write` fills a hidden temp file in the destination directory and hands the
+/// finished file back, which is then fsynced, renamed over `final_path` and followed by an fsync of the directory
+/// A failed write removes the temp file and leaves `final_path` untouched
+pub fn write_atomically<F>(final_path: &Path, write: F) -> anyhow::Result<()>
+where
+    F: FnOnce(fs::File) -> anyhow::Result<fs::File>,
+{
+    let temp_path = temp_path_for(final_path);
+    // Owner-only until the caller widens it, so a half written archive is never readable by others
+    let result = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp_path)
+        .map_err(anyhow::Error::from)
+        .and_then(write)
+        .and_then(|file| Ok(file.sync_all()?))
+        .and_then(|_| Ok(fs::rename(&temp_path, final_path)?));
+
+    if let Err(error) = result {
+        fs::remove_file(&temp_path).ok();
+        return Err(error);
+    }
+
+    let directory = final_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    fs::File::open(directory)?.sync_all()?;
+    Ok(())
+}
+
+/// Remove temp files left behind in a provided directory by a run that crashed mid-write
+pub fn cleanup_stray_temp_files(dir_path: &str) -> anyhow::Result<Vec<path::PathBuf>> {
+    let stray_files: Vec<path::PathBuf> = directory_files(dir_path)?
+        .into_iter()
+        .filter(|file| {
+            file.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with('.') && name.ends_with(TEMP_ARCHIVE_SUFFIX))
+        })
+        .collect();
+
+    for file in &stray_files {
+        fs::remove_file(file)?;
+    }
+    Ok(stray_files)
+}
+
+/// Length and SHA-256 (lowercase hex) of everything a provided reader yields
+pub fn reader_digest<R: io::Read>(mut reader: R) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let length = io::copy(&mut reader, &mut hasher)?;
+    Ok((length, format!("{:x}", hasher.finalize())))
+}
+
+/// Length and SHA-256 of a provided file
+pub fn file_digest(file_path: &Path) -> anyhow::Result<(u64, String)> {
+    let file = fs::File::open(file_path)
+        .map_err(|error| anyhow::anyhow!("Unable to open '{}': {}", file_path.display(), error))?;
+    Ok(reader_digest(file)?)
+}
+
+/// Name, length and SHA-256 of one entry streamed out of an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntryDigest {
+    pub name: String,
+    pub length: u64,
+    pub sha256: String,
+}
+
+/// Reopen an archive with the reader matching its extension and stream every entry through SHA-256
+/// Fails on anything that cannot be fully decoded - truncated gzip streams, bad zip CRCs, short tar entries
+pub fn archive_entry_digests(archive_path: &Path) -> anyhow::Result<Vec<ArchiveEntryDigest>> {
+    let archive_str = archive_path.to_string_lossy();
+    let archive_type = ArchiveType::from_archive_path(&archive_str)
+        .ok_or_else(|| anyhow::anyhow!("'{}' is not a tar, tar.gz, zip, gz, zst or xz archive", archive_str))?;
+    let archive_file = fs::File::open(archive_path)?;
+
+    match archive_type {
+        ArchiveType::Tar => tar_entry_digests(tar::Archive::new(archive_file)),
+        // Multi-member aware - large archives are written as parallel gzip members
+        ArchiveType::TarGunzip => tar_entry_digests(tar::Archive::new(flate2::read::MultiGzDecoder::new(archive_file))),
+        ArchiveType::Zip => {
+            let mut zip_archive = zip::ZipArchive::new(archive_file)?;
+            let mut digests = Vec::new();
+            for index in 0..zip_archive.len() {
+                let entry = zip_archive.by_index(index)?;
+                let name = entry.name().to_string();
+                let (length, sha256) = reader_digest(entry)?;
+                digests.push(ArchiveEntryDigest { name, length, sha256 });
+            }
+            Ok(digests)
+        }
+        ArchiveType::Gzip => {
+            let mut decoder = flate2::read::MultiGzDecoder::new(archive_file);
+            let (length, sha256) = reader_digest(&mut decoder)?;
+            let name = decoder
+                .header()
+                .and_then(|header| header.filename())
+                .map(|name| String::from_utf8_lossy(name).to_string())
+                .unwrap_or_else(|| single_file_entry_name(&archive_str));
+            Ok(vec![ArchiveEntryDigest { name, length, sha256 }])
+        }
+        ArchiveType::Zstd => {
+            let (length, sha256) = reader_digest(zstd::stream::read::Decoder::new(archive_file)?)?;
+            Ok(vec![ArchiveEntryDigest { name: single_file_entry_name(&archive_str), length, sha256 }])
+        }
+        ArchiveType::Xz => {
+            let (length, sha256) = reader_digest(xz2::read::XzDecoder::new(archive_file))?;
+            Ok(vec![ArchiveEntryDigest { name: single_file_entry_name(&archive_str), length, sha256 }])
+        }
+    }
+}
+
+/// Name of the file a single file stream was made from, going by the archive name
+pub(crate) fn single_file_entry_name(archive_path: &str) -> String {
+    let family = split_archive_name(archive_path)
+        .map(|(family, _, _)| family)
+        .unwrap_or_else(|| archive_path.to_string());
+    Path::new(&family)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or(family)
+}
+
+fn tar_entry_digests<R: io::Read>(mut tar_archive: tar::Archive<R>) -> anyhow::Result<Vec<ArchiveEntryDigest>> {
+    let mut digests = Vec::new();
+    for entry in tar_archive.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let expected_length = entry.header().size()?;
+        let (length, sha256) = reader_digest(entry)?;
+        if length != expected_length {
+            return Err(anyhow::anyhow!("Entry '{}' is truncated: {} of {} bytes", name, length, expected_length));
+        }
+        digests.push(ArchiveEntryDigest { name, length, sha256 });
+    }
+    Ok(digests)
+}
+
+/// Check that a freshly written single-file archive holds exactly the source snapshot
+pub fn verify_archive_against_source(archive_path: &Path, source_length: u64, source_sha256: &str) -> anyhow::Result<()> {
+    let digests = archive_entry_digests(archive_path)?;
+    let [entry] = digests.as_slice() else {
+        return Err(anyhow::anyhow!("Expected a single entry in '{}', found {}", archive_path.display(), digests.len()));
+    };
+
+    if entry.length != source_length {
+        return Err(anyhow::anyhow!("Entry '{}' is {} bytes, source snapshot was {} bytes", entry.name, entry.length, source_length));
+    }
+    if entry.sha256 != source_sha256 {
+        return Err(anyhow::anyhow!("Entry '{}' checksum {} does not match source snapshot {}", entry.name, entry.sha256, source_sha256));
+    }
+    Ok(())
+}
+
+/// Check that the named entry of an archive holds exactly the source snapshot - for archives appended to
+pub fn verify_archive_entry(archive_path: &Path, entry_name: &str, source_length: u64, source_sha256: &str) -> anyhow::Result<()> {
+    let digests = archive_entry_digests(archive_path)?;
+    let entry = digests
+        .iter()
+        .find(|entry| entry.name == entry_name)
+        .ok_or_else(|| anyhow::anyhow!("No entry '{}' in '{}'", entry_name, archive_path.display()))?;
+
+    if entry.length != source_length {
+        return Err(anyhow::anyhow!("Entry '{}' is {} bytes, source snapshot was {} bytes", entry.name, entry.length, source_length));
+    }
+    if entry.sha256 != source_sha256 {
+        return Err(anyhow::anyhow!("Entry '{}' checksum {} does not match source snapshot {}", entry.name, entry.sha256, source_sha256));
+    }
+    Ok(())
 }
 
 /// Get a file extension type from a provided file path
@@ -250,55 +1057,1006 @@ pub fn get_file_extension(file_path: &str) -> String {
         .unwrap_or_else(|| "unknown".to_string())
 }
 
+/// Archive name suffixes written by the archive backends, longest first so `.tar.gz` wins over `.gz`
+const ARCHIVE_SUFFIXES: [&str; 12] = [
+    ".tar.gz.age", ".tar.age", ".zip.age", ".gz.age", ".zst.age", ".xz.age",
+    ".tar.gz", ".tar", ".zip", ".gz", ".zst", ".xz",
+];
+
+/// Split an archive path into the original file it came from, the date embedded in its name and its rotation number
+/// e.g. `./logs/app.log_2025_09_26.tar.gz` -> (`./logs/app.log`, Some(2025-09-26), None),
+/// `./logs/app.log.2.gz` -> (`./logs/app.log`, None, Some(2))
+/// Returns None when the path does not carry one of the archive suffixes
+pub fn split_archive_name(file_path: &str) -> Option<(String, Option<NaiveDate>, Option<u32>)> {
+    let stem = ARCHIVE_SUFFIXES
+        .iter()
+        .find_map(|suffix| file_path.strip_suffix(suffix))?;
+
+    // "_YYYY_MM_DD" is 11 chars long - see get_date()
+    if stem.len() > 11 && stem.is_char_boundary(stem.len() - 11) {
+        let (family, date_part) = stem.split_at(stem.len() - 11);
+        if let Ok(date) = NaiveDate::parse_from_str(date_part, "_%Y_%m_%d") {
+            return Some((family.to_string(), Some(date), None));
+        }
+    }
+
+    // Numbered scheme - "app.log.2"
+    if let Some((family, number)) = stem.rsplit_once('.')
+        && !number.is_empty()
+        && number.bytes().all(|byte| byte.is_ascii_digit())
+    {
+        return Some((family.to_string(), None, number.parse().ok()));
+    }
+
+    Some((stem.to_string(), None, None))
+}
+
+/// One archive of a log family
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveListing {
+    pub archive: path::PathBuf,
+    /// Date embedded in the name - None for numbered archives
+    pub date: Option<NaiveDate>,
+    /// Rotation number of a numbered archive - 1 is the newest
+    pub index: Option<u32>,
+    pub bytes: u64,
+    pub modified: SystemTime,
+    /// What the directory's catalog knows about the archive
+    pub catalog: Option<catalog::CatalogEntry>,
+}
+
+impl ArchiveListing {
+    /// Ordering from oldest to newest - the date in the name, then the rotation number (higher is older), then the
+    /// rotation time in the catalog, then mtime
+    pub fn age_key(&self) -> ArchiveAgeKey {
+        archive_age_key(self.date, self.index, self.catalog.as_ref().and_then(|entry| entry.rotated_at()), self.modified)
+    }
+}
+
+/// Sort key of an archive, oldest first - see `ArchiveListing::age_key`
+pub type ArchiveAgeKey = (Option<NaiveDate>, Option<std::cmp::Reverse<u32>>, Option<DateTime<Utc>>, SystemTime);
+
+fn archive_age_key(date: Option<NaiveDate>, index: Option<u32>, rotated: Option<DateTime<Utc>>, modified: SystemTime) -> ArchiveAgeKey {
+    (date, index.map(std::cmp::Reverse), rotated, modified)
+}
+
+/// Group the archives of a file list by log family, newest first
+/// Archives are ordered by the date embedded in their name or their rotation number, falling back to the rotation
+/// time recorded in the catalog and then mtime for ties
+pub fn archive_families(file_list: &[path::PathBuf]) -> BTreeMap<String, Vec<ArchiveListing>> {
+    let mut families: BTreeMap<String, Vec<ArchiveListing>> = BTreeMap::new();
+    let mut catalogs = catalog::CatalogIndex::default();
+
+    for file in file_list {
+        let Some(file_str) = file.to_str() else { continue };
+        let Some((family, date, index)) = split_archive_name(file_str) else { continue };
+        let metadata = fs::metadata(file).ok();
+        families.entry(family).or_default().push(ArchiveListing {
+            archive: file.clone(),
+            date,
+            index,
+            bytes: metadata.as_ref().map(|metadata| metadata.len()).unwrap_or(0),
+            modified: metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+            catalog: catalogs.lookup(file).cloned(),
+        });
+    }
+
+    for archives in families.values_mut() {
+        archives.sort_by_key(|listing| std::cmp::Reverse(listing.age_key()));
+    }
+    families
+}
+
+/// Group archives by log family and return every archive beyond the newest `keep_count`
+pub fn count_retention_candidates(file_list: &[path::PathBuf], keep_count: usize) -> Vec<path::PathBuf> {
+    archive_families(file_list)
+        .into_values()
+        .flat_map(|archives| archives.into_iter().skip(keep_count).map(|listing| listing.archive))
+        .collect()
+}
+
+/// Parse a human readable size such as `512`, `64K`, `500M` or `2G` into bytes (binary multiples)
+pub fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let size = size.trim();
+    let split_at = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (digits, unit) = size.split_at(split_at);
+
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size '{}'", size))?;
+    let multiplier: u64 = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(anyhow::anyhow!("Invalid size unit '{}' in '{}'", unit, size)),
+    };
+
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow::anyhow!("Size '{}' is too large", size))
+}
+
+/// Outcome of planning a directory against its disk budget
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskBudgetPlan {
+    pub usage_before: u64,
+    pub usage_after: u64,
+    /// Archives to delete, oldest first
+    pub remove: Vec<path::PathBuf>,
+    /// Active files to rotate early, largest first
+    pub force_rotate: Vec<path::PathBuf>,
+}
+
+/// Work out which archives (oldest first) and then which active files (largest first)
+/// have to go for the directory to fit in `max_total_size` bytes
+/// Force-rotated files are projected as fully reclaimed - the size of their new archive is not known up front
+pub fn plan_disk_budget(file_list: &[path::PathBuf], max_total_size: u64, force_rotate: bool) -> DiskBudgetPlan {
+    let mut archives = Vec::new();
+    let mut active_files = Vec::new();
+    let mut usage_before: u64 = 0;
+    let mut catalogs = catalog::CatalogIndex::default();
+
+    for file in file_list {
+        let Ok(metadata) = fs::metadata(file) else { continue };
+        let Some(file_str) = file.to_str() else { continue };
+        usage_before += metadata.len();
+
+        if let Some((_, date, index)) = split_archive_name(file_str) {
+            let mtime = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            let rotated = catalogs.lookup(file).and_then(|entry| entry.rotated_at());
+            archives.push((archive_age_key(date, index, rotated, mtime), metadata.len(), file.clone()));
+        }
+        else if get_file_extension(file_str) != "unknown" {
+            active_files.push((metadata.len(), file.clone()));
+        }
+    }
+
+    let mut usage_after = usage_before;
+    let mut remove = Vec::new();
+    let mut rotate = Vec::new();
+
+    // Oldest archives first - undated archives sort before dated ones and fall back on the catalog, then mtime
+    archives.sort_by_key(|(age, _, _)| *age);
+    for (_, size, file) in archives {
+        if usage_after <= max_total_size {
+            break;
+        }
+        usage_after -= size;
+        remove.push(file);
+    }
+
+    if force_rotate {
+        active_files.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+        for (size, file) in active_files {
+            if usage_after <= max_total_size || size == 0 {
+                break;
+            }
+            usage_after -= size;
+            rotate.push(file);
+        }
+    }
+
+    DiskBudgetPlan { usage_before, usage_after, remove, force_rotate: rotate }
+}
+
+/// Total size in bytes of the provided files
+pub fn directory_usage(file_list: &[path::PathBuf]) -> u64 {
+    file_list
+        .iter()
+        .filter_map(|file| fs::metadata(file).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Space to leave free on the destination filesystem when writing archives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreeSpaceReserve {
+    Bytes(u64),
+    Percent(u8),
+}
+
+impl FromStr for FreeSpaceReserve {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().strip_suffix('%') {
+            Some(percent) => {
+                let percent: u8 = percent
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid reserve percentage '{}'", s))?;
+                if percent > 100 {
+                    return Err(anyhow::anyhow!("Reserve percentage '{}' is above 100%", s));
+                }
+                Ok(FreeSpaceReserve::Percent(percent))
+            }
+            None => Ok(FreeSpaceReserve::Bytes(parse_size(s)?)),
+        }
+    }
+}
+
+/// What to do with a file whose archive would not fit on the destination filesystem
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum LowSpaceAction {
+    /// Leave the file untouched
+    Skip,
+    /// Truncate the file without archiving it to free up space
+    Emergency,
+}
+
+/// Pre-flight free space check applied before every archive write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeSpaceGuard {
+    pub reserve: FreeSpaceReserve,
+    pub on_low_space: LowSpaceAction,
+}
+
+/// Available (to unprivileged users) and total bytes of the filesystem holding a provided path - via statvfs
+pub fn filesystem_space(file_path: &Path) -> anyhow::Result<(u64, u64)> {
+    let c_path = std::ffi::CString::new(file_path.as_os_str().as_encoded_bytes())?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    // SAFETY: c_path is a valid nul terminated string and stats is a properly sized out pointer
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return Err(anyhow::Error::new(io::Error::last_os_error())
+            .context(format!("statvfs failed for '{}'", file_path.display())));
+    }
+
+    let fragment_size = stats.f_frsize as u64;
+    Ok((stats.f_bavail as u64 * fragment_size, stats.f_blocks as u64 * fragment_size))
+}
+
+/// Check whether archiving a provided file fits on its destination filesystem while honouring the reserve
+/// Returns the reason when it does not fit. The archive is budgeted as the full source size plus one tar record,
+/// since compression ratios are not known up front
+pub fn check_free_space(file_path: &str, reserve: FreeSpaceReserve) -> anyhow::Result<Option<String>> {
+    let source_size = fs::metadata(file_path)?.len();
+    let destination = Path::new(file_path)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    check_free_space_for(destination, source_size + 10240, reserve)
+}
+
+/// Check whether `required` bytes fit in a provided directory's filesystem while honouring the reserve
+pub fn check_free_space_for(destination: &Path, required: u64, reserve: FreeSpaceReserve) -> anyhow::Result<Option<String>> {
+    let (available, total) = filesystem_space(destination)?;
+
+    let reserve_bytes = match reserve {
+        FreeSpaceReserve::Bytes(bytes) => bytes,
+        FreeSpaceReserve::Percent(percent) => total / 100 * percent as u64,
+    };
+    let usable = available.saturating_sub(reserve_bytes);
+
+    if required > usable {
+        Ok(Some(format!(
+            "low disk space: archive needs up to {} bytes, {} bytes available above the {} byte reserve",
+            required, usable, reserve_bytes,
+        )))
+    }
+    else {
+        Ok(None)
+    }
+}
+
+/// Action taken (or planned) for a single file, used for the end of run report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Archive,
+    Remove,
+    Truncate,
+    Unchanged,
+    Skipped,
+}
+
+impl Action {
+    /// Lower case name for structured output, e.g. `remove`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Archive => "archive",
+            Action::Remove => "remove",
+            Action::Truncate => "truncate",
+            Action::Unchanged => "unchanged",
+            Action::Skipped => "skipped",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Action::Archive => "Archiving",
+            Action::Remove => "Removing",
+            Action::Truncate => "Truncating",
+            Action::Unchanged => "Unchanged",
+            Action::Skipped => "Skipped",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// One line of the run report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionRecord {
+    pub file: path::PathBuf,
+    pub action: Action,
+    /// Archive written for the file, if any
+    pub archive: Option<path::PathBuf>,
+    /// Size of the source file before the action
+    pub bytes: u64,
+    /// Why the action deviated from the plan (low disk space, backend errors, ...)
+    pub reason: Option<String>,
+    /// Redactions per rule applied while archiving
+    pub redactions: RedactionCounts,
+}
+
+impl ActionRecord {
+    pub fn new(file: &Path, action: Action) -> ActionRecord {
+        ActionRecord {
+            file: file.to_path_buf(),
+            action,
+            archive: None,
+            bytes: fs::metadata(file).map(|metadata| metadata.len()).unwrap_or(0),
+            reason: None,
+            redactions: RedactionCounts::new(),
+        }
+    }
+}
+
+impl fmt::Display for ActionRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file = self.file.to_string_lossy();
+        write!(f, "File: {} | Action Type: {} | File Extension: {}", file, self.action, get_file_extension(&file))?;
+        if let Some(archive) = &self.archive {
+            write!(f, " | Archive: {}", archive.display())?;
+        }
+        if !self.redactions.is_empty() {
+            write!(f, " | Redactions: {}", redact::DisplayCounts(&self.redactions))?;
+        }
+        if let Some(reason) = &self.reason {
+            write!(f, " | Reason: {}", reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Options for a rotation run that go beyond the bucketing threshold and archive method
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Free space check before writing archives - disabled when None
+    pub free_space_guard: Option<FreeSpaceGuard>,
+    /// Re-read every new archive and compare it with the source before truncating
+    pub verify: bool,
+    /// Permissions of new archives - the source file's mode when None
+    pub archive_mode: Option<u32>,
+    /// Permissions applied to the live file once it has been truncated after archiving - left as is when None
+    pub create_mode: Option<u32>,
+    /// Record archive and source checksums in the per-directory manifest hash chain
+    pub manifest: bool,
+    /// Encrypt new archives to these age recipients - archives stay in plain text when empty
+    pub recipients: Vec<age::x25519::Recipient>,
+    /// Scrub secrets and PII from the content going into archives - disabled when None
+    pub redactor: Option<Redactor>,
+    /// Write every file due for archiving into shared bundle archives instead of one archive per file
+    pub bundle: Option<BundleOptions>,
+    /// Add to the day's archive when it already exists instead of replacing it
+    pub append: bool,
+    /// Naming of per-file archives
+    pub naming: NamingScheme,
+    /// Keep the latest rotation as plain `app.log.1` and only compress it on the next run - needs numbered naming
+    pub delay_compress: bool,
+    /// Worker threads for processing files - 0 and 1 both mean one file at a time
+    pub jobs: usize,
+    /// Compress large inputs on several cores - single threaded when None
+    pub parallel_compression: Option<ParallelCompression>,
+    /// Once set, files not started yet are skipped - the daemon's SIGTERM flag
+    pub shutdown: Option<&'static AtomicBool>,
+    /// Forward archive, remove and truncate actions to syslog - disabled when None
+    pub syslog: Option<Arc<SyslogSender>>,
+    /// Rules deciding each file's action - the classic preset when None
+    pub rules: Option<Arc<rules::RuleSet>>,
+}
+
 /// Remove a provided file via it's path
 pub fn remove_file(file_path: &str) {
-    fs::remove_file(file_path.to_string()).unwrap();
+    fs::remove_file(file_path).unwrap();
+}
+
+/// Forward an action record to syslog when it is enabled - a failed send is reported and the run goes on
+pub fn forward_record(record: &ActionRecord, options: &RunOptions) {
+    if let Some(syslog) = &options.syslog
+        && let Err(error) = syslog.send_record(record)
+    {
+        eprintln!("Unable to forward {} to syslog: {}", record.file.display(), error);
+    }
+}
+
+/// Remove a file as part of retention - archives are recorded in the manifest first when it is enabled
+pub fn remove_retained_file(file: &Path, options: &RunOptions) -> anyhow::Result<()> {
+    if options.manifest && file.to_str().and_then(split_archive_name).is_some() {
+        manifest::remove_archive_recorded(file)?;
+    }
+    else {
+        fs::remove_file(file)?;
+    }
+    if file.to_str().and_then(split_archive_name).is_some() {
+        catalog::remove_entry(file)?;
+    }
+    Ok(())
+}
+
+/// `remove_retained_file` as a report record - a failed removal is recorded as skipped with the reason
+pub fn removal_record(file: &Path, options: &RunOptions) -> ActionRecord {
+    let mut record = ActionRecord::new(file, Action::Remove);
+    if let Err(error) = remove_retained_file(file, options) {
+        record.action = Action::Skipped;
+        record.reason = Some(format!("{:#}", error));
+    }
+    record
+}
+
+/// What a run would do to a single file - the building block of `plan` and dry runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedAction {
+    pub file: path::PathBuf,
+    pub action: Action,
+    pub extension: String,
+    pub bytes: u64,
+    /// Archive method, for files due for archiving
+    pub method: Option<String>,
+    /// Archive the file would be written to
+    pub archive: Option<path::PathBuf>,
+    /// Bundle the file would be written into
+    pub bundle: Option<path::PathBuf>,
+    /// Rule behind the action - `keep-days`, `keep-count` or `max-total-size`
+    pub rule: String,
+    /// Why the action deviates from the rule (low disk space, ...)
+    pub reason: Option<String>,
+    /// Rule of the rule set behind a `keep-days` action - None when no rule matched
+    pub decided_by: Option<String>,
+}
+
+impl PlannedAction {
+    pub fn new(file: &Path, action: Action, rule: &str) -> PlannedAction {
+        let file_path = file.to_string_lossy();
+        PlannedAction {
+            file: file.to_path_buf(),
+            action,
+            extension: get_file_extension(&file_path),
+            bytes: fs::metadata(file).map(|metadata| metadata.len()).unwrap_or(0),
+            method: None,
+            archive: None,
+            bundle: None,
+            rule: rule.to_string(),
+            reason: None,
+            decided_by: None,
+        }
+    }
+}
+
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "File: {}", self.file.display())?;
+        if let Some(method) = &self.method {
+            write!(f, " | Status: {}", method)?;
+        }
+        write!(f, " | Action Type: {}", self.action)?;
+        if self.rule != "keep-days" {
+            write!(f, " ({})", self.rule)?;
+        }
+        write!(f, " | File Extension: {}", self.extension)?;
+        if let Some(bundle) = &self.bundle {
+            write!(f, " | Bundle: {}", bundle.display())?;
+        }
+        if let Some(reason) = &self.reason {
+            write!(f, " | Reason: {}", reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Plan the bucketed action for a single file without touching it
+pub fn plan_file(file: &Path, threshold_days: i64, archive_type: &ArchiveType, options: &RunOptions) -> anyhow::Result<PlannedAction> {
+    if is_internal_file(file) {
+        return Ok(PlannedAction::new(file, Action::Unchanged, "keep-days"));
+    }
+    let explanation = rule_set(options).explain(file, threshold_days)?;
+    let action = explanation.action;
+    let mut planned = PlannedAction::new(file, action, "keep-days");
+    planned.decided_by = explanation.decided_by;
+    if action != Action::Archive {
+        return Ok(planned);
+    }
+
+    planned.method = Some(archive_type.as_str().to_string());
+    if let Some(bundle_options) = &options.bundle {
+        let key = bundle::bundle_key(&bundle_options.directory, file, bundle_options.group);
+        planned.bundle = Some(bundle::bundle_path_for(&bundle_options.directory, &key, archive_type, options));
+        return Ok(planned);
+    }
+    let file_path = utf8_path(file)?;
+    planned.archive = Some(match options.delay_compress {
+        true => delayed_rotation_path(file_path),
+        false => path::PathBuf::from(archive_path_with_options(file_path, archive_type, options)),
+    });
+
+    if let Some(guard) = options.free_space_guard {
+        let low_space = match check_free_space(file_path, guard.reserve) {
+            Ok(None) => None,
+            Ok(Some(reason)) => Some((match guard.on_low_space {
+                LowSpaceAction::Skip => Action::Skipped,
+                LowSpaceAction::Emergency => Action::Truncate,
+            }, reason)),
+            Err(error) => Some((Action::Skipped, format!("{:#}", error))),
+        };
+        if let Some((action, reason)) = low_space {
+            planned.action = action;
+            planned.method = None;
+            planned.archive = None;
+            planned.reason = Some(reason);
+        }
+    }
+    Ok(planned)
+}
+
+/// Plan every file of a run, in file order
+pub fn plan_run(file_list: &[path::PathBuf], threshold_days: i64, archive_type: &ArchiveType, options: &RunOptions) -> Vec<PlannedAction> {
+    file_list
+        .iter()
+        .map(|file| {
+            plan_file(file, threshold_days, archive_type, options).unwrap_or_else(|error| {
+                let mut planned = PlannedAction::new(file, Action::Skipped, "keep-days");
+                planned.reason = Some(format!("{:#}", error));
+                planned
+            })
+        })
+        .collect()
+}
+
+/// Plan the keep-count pass over the current directory listing
+pub fn plan_count_retention(dir_path: &str, keep_count: usize) -> anyhow::Result<Vec<PlannedAction>> {
+    let file_list = gather_files_from_directory(dir_path)?;
+    Ok(count_retention_candidates(&file_list, keep_count)
+        .iter()
+        .map(|file| PlannedAction::new(file, Action::Remove, "keep-count"))
+        .collect())
+}
+
+/// Plan the disk budget pass over the current directory listing
+pub fn plan_disk_budget_actions(dir_path: &str, max_total_size: u64, force_rotate: bool, archive_type: &ArchiveType, options: &RunOptions) -> anyhow::Result<(DiskBudgetPlan, Vec<PlannedAction>)> {
+    let file_list = gather_files_from_directory(dir_path)?;
+    let plan = plan_disk_budget(&file_list, max_total_size, force_rotate);
+    let mut actions: Vec<PlannedAction> = plan
+        .remove
+        .iter()
+        .map(|file| PlannedAction::new(file, Action::Remove, "max-total-size"))
+        .collect();
+    for file in &plan.force_rotate {
+        let mut planned = PlannedAction::new(file, Action::Archive, "max-total-size");
+        planned.method = Some(archive_type.as_str().to_string());
+        planned.archive = Some(path::PathBuf::from(archive_path_with_options(&file.to_string_lossy(), archive_type, options)));
+        actions.push(planned);
+    }
+    Ok((plan, actions))
 }
 
 /// Do not worry about testing this function - only renders a file list to stdout
 #[cfg_attr(coverage_nightly, coverage(off))]
-pub fn dry_run_details(file_list: Vec<path::PathBuf>, threshold_days: i64, archive_type: ArchiveType) {
-    for file in file_list {
-        let mut _temp_archive_check = "";
-        match archive_remove_truncate_file_bucketing(file.to_str().unwrap(), threshold_days).unwrap() {
-            0 => println!("File: {} | Status: {} | Action Type: Archiving | File Extension: {}",
-                          file.to_str().unwrap(),
-                          archive_type.as_str(),
-                          get_file_extension(file.to_str().unwrap()),
-            ),
-            1 => println!("File: {} | Action Type: Removing | File Extension: {}",
-                          file.to_str().unwrap(),
-                          get_file_extension(file.to_str().unwrap()),
-            ),
-            2 => println!("File: {} | Action Type: Truncating | File Extension: {}",
-                          file.to_str().unwrap(),
-                          get_file_extension(file.to_str().unwrap()),
-            ),
-            3 => println!("File: {} | Action Type: Unchanged | File Extension: {}",
-                          file.to_str().unwrap(),
-                          get_file_extension(file.to_str().unwrap()),
-            ),
-            _ => println!("Base Case Error - Was unable to determine action type"),
+pub fn dry_run_details(file_list: Vec<path::PathBuf>, threshold_days: i64, archive_type: ArchiveType, options: &RunOptions) {
+    for planned in plan_run(&file_list, threshold_days, &archive_type, options) {
+        println!("{}", planned);
+    }
+}
+
+/// Apply the bucketed action to every file and return the report, in file order
+/// Files are processed on up to `options.jobs` worker threads
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn actual_run(file_list: Vec<path::PathBuf>, threshold_days: i64, archive_type: ArchiveType, options: &RunOptions) -> Vec<ActionRecord> {
+    let results = run_jobs(&file_list, options.jobs, |file| process_file(file, threshold_days, &archive_type, options));
+
+    // Files left for their bundle, with their position in the report
+    let bundled: Vec<(usize, path::PathBuf)> = results
+        .iter()
+        .enumerate()
+        .filter(|(_, record)| record.is_none())
+        .map(|(index, _)| (index, file_list[index].clone()))
+        .collect();
+    let mut report: Vec<Option<ActionRecord>> = results;
+
+    if let Some(bundle_options) = &options.bundle {
+        let files: Vec<path::PathBuf> = bundled.iter().map(|(_, file)| file.clone()).collect();
+        let records = if shutdown_requested(options) {
+            files
+                .iter()
+                .map(|file| {
+                    let mut record = ActionRecord::new(file, Action::Skipped);
+                    record.reason = Some("shutdown requested".to_string());
+                    record
+                })
+                .collect()
+        }
+        else {
+            bundle::bundle_files(&bundle_options.directory, &files, bundle_options.group, archive_type, options)
+        };
+        for ((index, _), record) in bundled.iter().zip(records) {
+            report[*index] = Some(record);
+        }
+    }
+    let report: Vec<ActionRecord> = report.into_iter().flatten().collect();
+    for record in &report {
+        forward_record(record, options);
+    }
+    report
+}
+
+/// Apply the bucketed action to a single file - None when it is due for archiving into a bundle
+pub fn process_file(file: &Path, threshold_days: i64, archive_type: &ArchiveType, options: &RunOptions) -> Option<ActionRecord> {
+    if shutdown_requested(options) {
+        let mut record = ActionRecord::new(file, Action::Skipped);
+        record.reason = Some("shutdown requested".to_string());
+        return Some(record);
+    }
+    if is_internal_file(file) {
+        return Some(ActionRecord::new(file, Action::Unchanged));
+    }
+
+    let action = match rule_set(options).decide(file, threshold_days) {
+        Ok(action) => action,
+        Err(error) => {
+            let mut record = ActionRecord::new(file, Action::Skipped);
+            record.reason = Some(format!("{:#}", error));
+            return Some(record);
+        }
+    };
+    let record = match action {
+        Action::Archive if options.bundle.is_some() => return None,
+        Action::Archive => archive_with_guard(file, archive_type.clone(), options),
+        Action::Remove => removal_record(file, options),
+        Action::Truncate => {
+            let mut record = ActionRecord::new(file, Action::Truncate);
+            if let Err(error) = truncate_file(file) {
+                record.action = Action::Skipped;
+                record.reason = Some(format!("Unable to truncate: {}", error));
+            }
+            record
+        }
+        _ => ActionRecord::new(file, Action::Unchanged),
+    };
+    Some(record)
+}
+
+/// A provided file's path as a string, which the archive backends work on - names that are not valid UTF-8 are refused
+pub fn utf8_path(file: &Path) -> anyhow::Result<&str> {
+    file.to_str()
+        .ok_or_else(|| anyhow::anyhow!("File name '{}' is not valid UTF-8", file.display()))
+}
+
+/// Rules deciding the actions of a run
+pub fn rule_set(options: &RunOptions) -> &rules::RuleSet {
+    match options.rules.as_deref() {
+        Some(rule_set) => rule_set,
+        None => rules::default_rules(),
+    }
+}
+
+/// Whether the run was asked to stop before its next file
+pub fn shutdown_requested(options: &RunOptions) -> bool {
+    options.shutdown.is_some_and(|shutdown| shutdown.load(Ordering::SeqCst))
+}
+
+/// Run `work` over every item on up to `jobs` threads and return the results in item order
+/// Workers pull the next item off a shared index, so one large file does not hold up the rest of a batch
+pub fn run_jobs<T, R, F>(items: &[T], jobs: usize, work: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if jobs <= 1 || items.len() <= 1 {
+        return items.iter().map(work).collect();
+    }
+
+    let next_item = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(items.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next_item.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(index) else { break };
+                    let result = work(item);
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every item is processed by a worker"))
+        .collect()
+}
+
+/// Archive a single file, applying the free space guard first when one is configured
+pub fn archive_with_guard(file: &Path, archive_type: ArchiveType, options: &RunOptions) -> ActionRecord {
+    let mut record = ActionRecord::new(file, Action::Archive);
+    let file_path = match utf8_path(file) {
+        Ok(file_path) => file_path,
+        Err(error) => {
+            record.action = Action::Skipped;
+            record.reason = Some(format!("{:#}", error));
+            return record;
+        }
+    };
+
+    if let Some(guard) = options.free_space_guard {
+        match check_free_space(file_path, guard.reserve) {
+            Ok(None) => {}
+            Ok(Some(reason)) => {
+                match guard.on_low_space {
+                    LowSpaceAction::Skip => record.action = Action::Skipped,
+                    LowSpaceAction::Emergency => match truncate_file(file_path) {
+                        Ok(()) => record.action = Action::Truncate,
+                        Err(error) => {
+                            record.action = Action::Skipped;
+                            record.reason = Some(format!("{} - unable to truncate: {}", reason, error));
+                            return record;
+                        }
+                    },
+                }
+                record.reason = Some(reason);
+                return record;
+            }
+            Err(error) => {
+                record.action = Action::Skipped;
+                record.reason = Some(format!("{:#}", error));
+                return record;
+            }
+        }
+    }
+
+    match archive_file(file_path, archive_type, options) {
+        Ok(outcome) => {
+            record.archive = Some(path::PathBuf::from(outcome.archive));
+            record.redactions = outcome.redactions;
+        }
+        Err(error) => {
+            record.action = Action::Skipped;
+            record.reason = Some(format!("{:#}", error));
         }
     }
+    record
 }
 
+/// Print the run report, one line per file
 #[cfg_attr(coverage_nightly, coverage(off))]
-pub fn actual_run(file_list: Vec<path::PathBuf>, threshold_days: i64, archive_type: ArchiveType) {
-    for file in file_list {
-        match archive_remove_truncate_file_bucketing(file.to_str().unwrap(), threshold_days).unwrap() {
-            0 => archive_selection_and_process(file.to_str().unwrap(), archive_type.clone()),
-            1 => remove_file(file.to_str().unwrap()),
-            2 => truncate_file(file.to_str().unwrap()),
-            3 => println!("File: {} | Action Type: Unchanged | File Extension: {}",
-                          file.to_str().unwrap(),
-                          get_file_extension(file.to_str().unwrap()),
-            ),
-            _ => println!("Base Case Error - Was unable to determine action type"),
+pub fn print_report(report: &[ActionRecord]) {
+    for record in report {
+        println!("{}", record);
+    }
+}
+
+/// Count-based retention pass - keeps only the newest `keep_count` archives per log family
+/// Runs against a fresh listing of the directory so archives created by this run are counted. Returns the removals
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn apply_count_retention(dir_path: &str, keep_count: usize, options: &RunOptions, dry_run: bool) -> anyhow::Result<Vec<ActionRecord>> {
+    let mut report = Vec::new();
+    for planned in plan_count_retention(dir_path, keep_count)? {
+        if dry_run {
+            println!("{}", planned);
+        }
+        else {
+            let record = removal_record(&planned.file, options);
+            forward_record(&record, options);
+            report.push(record);
+        }
+    }
+    Ok(report)
+}
+
+/// Disk budget pass - deletes the oldest archives, then optionally force-rotates the largest active files,
+/// until the directory fits in `max_total_size` bytes. Returns what was removed and archived
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn apply_disk_budget(dir_path: &str, max_total_size: u64, force_rotate: bool, archive_type: ArchiveType, options: &RunOptions, dry_run: bool) -> anyhow::Result<Vec<ActionRecord>> {
+    let (plan, actions) = plan_disk_budget_actions(dir_path, max_total_size, force_rotate, &archive_type, options)?;
+    let mut report = Vec::new();
+
+    for planned in actions {
+        if dry_run {
+            println!("{}", planned);
+            continue;
+        }
+        let record = match planned.action {
+            Action::Remove => removal_record(&planned.file, options),
+            _ => archive_with_guard(&planned.file, archive_type.clone(), options),
+        };
+        forward_record(&record, options);
+        report.push(record);
+    }
+
+    if dry_run {
+        println!("Disk Budget: {} bytes | Usage Before: {} bytes | Projected Usage After: {} bytes",
+                 max_total_size, plan.usage_before, plan.usage_after);
+    }
+    else {
+        let usage_after = directory_usage(&gather_files_from_directory(dir_path)?);
+        println!("Disk Budget: {} bytes | Usage Before: {} bytes | Usage After: {} bytes",
+                 max_total_size, plan.usage_before, usage_after);
+    }
+    Ok(report)
+}
+
+/// Re-check existing archives and print every entry with its length and checksum
+/// Returns the number of archives that failed to decode
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn verify_existing_archives(archive_list: &[path::PathBuf]) -> usize {
+    let mut failures = 0;
+    for archive in archive_list {
+        match archive_entry_digests(archive) {
+            Ok(digests) => {
+                for digest in digests {
+                    println!("Archive: {} | Status: OK | Entry: {} | Length: {} | SHA-256: {}",
+                             archive.display(), digest.name, digest.length, digest.sha256);
+                }
+            }
+            Err(error) => {
+                failures += 1;
+                println!("Archive: {} | Status: FAILED | Reason: {:#}", archive.display(), error);
+            }
         }
     }
+    failures
 }
 
+/// Check the manifest hash chain of a provided directory and print what was found
+/// Returns whether the chain is intact
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn verify_chain_details(dir_path: &str) -> anyhow::Result<bool> {
+    let report = manifest::verify_chain(Path::new(dir_path))?;
+    for problem in &report.problems {
+        println!("Chain: {} | Status: FAILED | Reason: {}", dir_path, problem);
+    }
+    for archive in &report.untracked {
+        println!("Archive: {} | Status: UNTRACKED | Reason: not recorded in the manifest", archive.display());
+    }
+    if report.is_intact() {
+        println!("Chain: {} | Status: OK | Entries: {}", dir_path, report.entries);
+    }
+    Ok(report.is_intact())
+}
+
+/// Print the archives of a directory grouped by log family, newest first
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn list_details(dir_path: &str) -> anyhow::Result<()> {
+    let families = archive_families(&gather_files_from_directory(dir_path)?);
+    if families.is_empty() {
+        println!("Directory: {} | Archives: 0", dir_path);
+    }
+    for (family, archives) in families {
+        println!("Family: {} | Archives: {} | Size: {} bytes",
+                 family, archives.len(), archives.iter().map(|listing| listing.bytes).sum::<u64>());
+        for listing in archives {
+            let modified: DateTime<Local> = listing.modified.into();
+            print!("    Archive: {} | Date: {} | Size: {} bytes | Modified: {}",
+                   listing.archive.display(),
+                   listing.date.map(|date| date.to_string()).unwrap_or_else(|| "-".to_string()),
+                   listing.bytes,
+                   modified.format("%Y-%m-%d %H:%M:%S"),
+            );
+            if let Some(entry) = &listing.catalog {
+                print!(" | Lines: {} | Ratio: {:.1} | Range: {} - {}",
+                       entry.lines,
+                       entry.compression_ratio,
+                       entry.first_timestamp.as_deref().unwrap_or("-"),
+                       entry.last_timestamp.as_deref().unwrap_or("-"),
+                );
+            }
+            println!();
+        }
+    }
+    Ok(())
+}
+
+/// Print every line matching a pattern in the archives of a directory, prefixed with the archive and entry name
+/// Archives that cannot be read are reported on stderr and the search goes on
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn grep_details(dir_path: &str, pattern: &str, since: Option<NaiveDate>, until: Option<NaiveDate>, jobs: usize) -> anyhow::Result<()> {
+    let pattern = regex::bytes::Regex::new(pattern)
+        .map_err(|error| anyhow::anyhow!("Invalid pattern '{}': {}", pattern, error))?;
+    let file_list = gather_files_from_directory(dir_path)?;
+    search::search_archives(&file_list, &pattern, since, until, jobs, |event| match event {
+        search::SearchEvent::Match(found) => println!("{}", found),
+        search::SearchEvent::Failed(archive, error) => eprintln!("Archive: {} | Skipped: {:#}", archive.display(), error),
+    });
+    Ok(())
+}
+
+/// Print what a directory holds and the runs recorded in its state file, newest first
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn status_details(dir_path: &str, runs: usize) -> anyhow::Result<()> {
+    let file_list = gather_files_from_directory(dir_path)?;
+    let (archives, others): (Vec<path::PathBuf>, Vec<path::PathBuf>) = file_list
+        .into_iter()
+        .partition(|file| file.to_str().and_then(split_archive_name).is_some());
+    let active: Vec<path::PathBuf> = others.into_iter().filter(|file| watch::is_active_log(file)).collect();
+    let state = state::read_state(Path::new(dir_path))?;
+
+    println!("Directory: {} | Active Files: {} ({} bytes) | Archives: {} ({} bytes) | Manifest: {}",
+             dir_path,
+             active.len(), directory_usage(&active),
+             archives.len(), directory_usage(&archives),
+             if manifest::manifest_path(Path::new(dir_path)).exists() { "yes" } else { "no" },
+    );
+    if state.runs.is_empty() {
+        println!("Last Run: never");
+    }
+    for run in state.runs.iter().rev().take(runs) {
+        println!("Run: {} | Finished: {} | Archived: {} ({} bytes) | Removed: {} ({} bytes) | Truncated: {} | Skipped: {} | Unchanged: {}",
+                 run.started, run.finished,
+                 run.archived, run.bytes_archived,
+                 run.removed, run.bytes_removed,
+                 run.truncated, run.skipped, run.unchanged,
+        );
+        for error in &run.errors {
+            println!("    Skipped: {}", error);
+        }
+    }
+    Ok(())
+}
+
+/// Validate a daemon policy file and print every target with its next run
+/// Returns whether every target directory exists
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn check_config_details(policy_path: &Path) -> anyhow::Result<bool> {
+    let targets = policy::load_policy(policy_path)?;
+    let mut all_present = true;
+    for target in &targets {
+        let present = Path::new(&target.directory).is_dir();
+        all_present &= present;
+        println!("Target: {} | Status: {} | Directory: {} | Schedule: {} | Archive Method: {} | Next Run: {}",
+                 target.name,
+                 if present { "OK" } else { "MISSING DIRECTORY" },
+                 target.directory,
+                 target.schedule,
+                 target.archive_type.as_str(),
+                 target.schedule.next_after(Local::now()).to_rfc3339(),
+        );
+    }
+    println!("Policy: {} | Targets: {} | Status: {}", policy_path.display(), targets.len(), if all_present { "OK" } else { "FAILED" });
+    Ok(all_present)
+}
+
+/// Restore the files of an archive and print where each went
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn restore_details(archive_path: &Path, options: &restore::RestoreOptions) -> anyhow::Result<()> {
+    for restored in restore::restore_archive(archive_path, options)? {
+        println!("Archive: {} | Action Type: Restored | File: {}", archive_path.display(), restored.display());
+    }
+    Ok(())
+}
+
+/// Print every rule checked against a file, with the values behind each condition
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn explain_details(file_path: &Path, threshold_days: i64, rule_set: &rules::RuleSet) -> anyhow::Result<()> {
+    println!("{}", rule_set.explain(file_path, threshold_days)?);
+    Ok(())
+}
+
+/// Decrypt an encrypted archive with the private key(s) in an age identity file
+#[cfg_attr(coverage_nightly, coverage(off))]
+pub fn decrypt_details(archive_path: &Path, identity_file: &Path, output: Option<&Path>) -> anyhow::Result<()> {
+    let output_path = crypto::decrypt_archive(archive_path, identity_file, output)?;
+    println!("Archive: {} | Action Type: Decrypted | Output: {}", archive_path.display(), output_path.display());
+    Ok(())
+}
 
 /// Fake test function
 pub fn test_add(left: u64, right: u64) -> u64 {
@@ -4,19 +4,60 @@ use logrotate::{
     ArchiveType,
     archive_remove_truncate_file_bucketing,
     gather_files_from_directory,
+    gather_files_recursively,
     get_file_mtime_diff,
     dry_run_details,
     actual_run,
+    apply_count_retention,
+    apply_disk_budget,
+    split_archive_name,
+    parse_size,
+    print_report,
+    cleanup_stray_temp_files,
+    FreeSpaceGuard,
+    FreeSpaceReserve,
+    LowSpaceAction,
+    NamingScheme,
+    RunOptions,
+    verify_existing_archives,
+    parse_mode,
+    verify_chain_details,
+    decrypt_details,
+    list_details,
+    status_details,
+    check_config_details,
+    restore_details,
+    grep_details,
+    explain_details,
+    plan_run,
+    plan_count_retention,
+    plan_disk_budget_actions,
+    bundle::{BundleGroup, BundleOptions},
+    crypto::parse_recipient,
+    daemon::run_daemon,
+    parallel::ParallelCompression,
+    redact::{builtin_rules, RedactionRule, Redactor},
+    restore::RestoreOptions,
+    rules::RuleSet,
+    state::{record_run, RunSummary},
+    syslog::{SyslogEndpoint, SyslogFacility, SyslogSender},
 };
 
 use anyhow::{Result};
+use chrono::{Local, NaiveDate};
 use std::fmt::Debug;
-use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Rotate / Archive files within provided directory", long_about = None)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// Perform a dry run without making any changes
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Perform a dry run without making any changes - the same as `plan`
     /// Will output files marked for deletion, archival, and truncation
     #[arg(
         long,
@@ -24,6 +65,14 @@ struct Cli {
     )]
     dry_run: bool,
 
+    /// Without a subcommand the rotation flags run `rotate`, kept for existing scripts and cron jobs
+    #[command(flatten)]
+    rotate: RotateArgs,
+}
+
+/// Settings of a rotation run, shared by `rotate`, `plan` and the flags without a subcommand
+#[derive(Args, Debug)]
+struct RotateArgs {
     /// Archival method to use
     #[arg(
         short = 'a',
@@ -31,7 +80,7 @@ struct Cli {
         value_enum,
         required = true,
     )]
-    archive_method: ArchiveType,
+    archive_method: Option<ArchiveType>,
 
     /// Directory to parse through
     #[arg(
@@ -40,7 +89,7 @@ struct Cli {
         value_name = "DIRECTORY",
         required = true,
     )]
-    directory: String,
+    directory: Option<String>,
     
     /// Number of days to keep archived files
     #[arg(
@@ -51,6 +100,369 @@ struct Cli {
         required = true,
     )]
     keep_days: u8,
+
+    /// Number of archives to keep per log family, newest first
+    /// Older archives beyond this count are removed on top of the keep-days rule
+    #[arg(
+        short = 'c',
+        long = "keep-count",
+        value_name = "COUNT",
+        required = false,
+    )]
+    keep_count: Option<usize>,
+
+    /// Only apply keep-count retention to archives, skipping the keep-days removal rule
+    #[arg(
+        long = "count-only",
+        requires = "keep_count",
+        required = false,
+    )]
+    count_only: bool,
+
+    /// Disk budget for the directory (active logs plus archives), e.g. 500M or 2G
+    /// Oldest archives are removed first until usage fits
+    #[arg(
+        long = "max-total-size",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        required = false,
+    )]
+    max_total_size: Option<u64>,
+
+    /// When the disk budget is still exceeded after removing archives, archive and truncate the largest active files
+    #[arg(
+        long = "force-rotate",
+        requires = "max_total_size",
+        required = false,
+    )]
+    force_rotate: bool,
+
+    /// Space to keep free on the destination filesystem when writing archives, in bytes (e.g. 1G) or percent (e.g. 10%)
+    /// Enables a statvfs pre-flight check before every archive write
+    #[arg(
+        long = "min-free",
+        value_name = "RESERVE",
+        required = false,
+    )]
+    min_free: Option<FreeSpaceReserve>,
+
+    /// What to do with files whose archive would not fit above the min-free reserve
+    #[arg(
+        long = "on-low-space",
+        value_enum,
+        default_value = "skip",
+        requires = "min_free",
+        required = false,
+    )]
+    on_low_space: LowSpaceAction,
+
+    /// Re-read every new archive and compare its length and checksum with the source before truncating it
+    #[arg(
+        long = "verify",
+        conflicts_with = "recipients",
+        required = false,
+    )]
+    verify: bool,
+
+    /// Octal permissions for new archives, e.g. 0640 - defaults to the mode of the file being archived
+    #[arg(
+        long = "archive-mode",
+        value_name = "MODE",
+        value_parser = parse_mode,
+        required = false,
+    )]
+    archive_mode: Option<u32>,
+
+    /// Octal permissions to set on the live file after it has been archived and truncated, e.g. 0640
+    #[arg(
+        long = "create-mode",
+        value_name = "MODE",
+        value_parser = parse_mode,
+        required = false,
+    )]
+    create_mode: Option<u32>,
+
+    /// Record SHA-256 checksums of every archive and its original content in a per-directory hash chain manifest
+    #[arg(
+        long = "manifest",
+        required = false,
+    )]
+    manifest: bool,
+
+    /// Encrypt archives to an age X25519 public key (age1...) as they are written, producing e.g. .tar.gz.age
+    /// Repeat to encrypt to several recipients
+    #[arg(
+        long = "recipient",
+        value_name = "RECIPIENT",
+        value_parser = parse_recipient,
+        required = false,
+    )]
+    recipients: Vec<age::x25519::Recipient>,
+
+    /// Scrub tokens, AWS keys, emails, card numbers and IP addresses from archived content with the built-in detectors
+    #[arg(
+        long = "redact",
+        required = false,
+    )]
+    redact: bool,
+
+    /// Custom redaction rule as NAME=REGEX, applied line by line before the built-in detectors
+    /// Repeat for several rules
+    #[arg(
+        long = "redact-rule",
+        value_name = "NAME=REGEX",
+        value_parser = RedactionRule::parse,
+        required = false,
+    )]
+    redact_rules: Vec<RedactionRule>,
+
+    /// Write every file due for archiving into one shared archive per run instead of one archive per file
+    /// Entries keep their path relative to the directory
+    #[arg(
+        long = "bundle",
+        required = false,
+    )]
+    bundle: bool,
+
+    /// Split the bundle into one archive per file type or per subdirectory
+    #[arg(
+        long = "bundle-group",
+        value_enum,
+        default_value = "none",
+        requires = "bundle",
+        required = false,
+    )]
+    bundle_group: BundleGroup,
+
+    /// Add to the day's archive (or bundle) when it already exists instead of replacing it
+    /// Entries whose name is taken get a numeric suffix, e.g. app.log.1
+    #[arg(
+        long = "append",
+        conflicts_with = "recipients",
+        required = false,
+    )]
+    append: bool,
+
+    /// Naming of per-file archives: dated (app.log_2025_09_26.tar.gz) or numbered (app.log.1.tar.gz, shifted every rotation)
+    /// Defaults to date, or numbered with --delay-compress
+    #[arg(
+        long = "naming",
+        value_enum,
+        value_name = "SCHEME",
+        conflicts_with = "append",
+        required = false,
+    )]
+    naming: Option<NamingScheme>,
+
+    /// Keep the latest rotation uncompressed as app.log.1 and compress it into app.log.2.<ext> on the next run
+    #[arg(
+        long = "delay-compress",
+        conflicts_with_all = ["recipients", "bundle"],
+        required = false,
+    )]
+    delay_compress: bool,
+
+    /// Number of files to process in parallel
+    #[arg(
+        short = 'j',
+        long = "jobs",
+        value_name = "N",
+        default_value = "1",
+        value_parser = clap::value_parser!(u16).range(1..),
+        required = false,
+    )]
+    jobs: u16,
+
+    /// Files at least this large are compressed on several cores (parallel gzip members, multithreaded zstd and xz)
+    #[arg(
+        long = "parallel-threshold",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        default_value = "256M",
+        required = false,
+    )]
+    parallel_threshold: u64,
+
+    /// Threads for compressing a single large file - defaults to the number of CPUs
+    #[arg(
+        long = "compress-threads",
+        value_name = "N",
+        value_parser = clap::value_parser!(u16).range(1..),
+        required = false,
+    )]
+    compress_threads: Option<u16>,
+
+    /// Send an RFC 5424 syslog message for every archived, removed and truncated file
+    #[arg(
+        long = "syslog",
+        required = false,
+    )]
+    syslog: bool,
+
+    /// Syslog destination - a Unix datagram socket path or udp://host:port
+    #[arg(
+        long = "syslog-endpoint",
+        value_name = "ENDPOINT",
+        default_value = "/dev/log",
+        requires = "syslog",
+        required = false,
+    )]
+    syslog_endpoint: SyslogEndpoint,
+
+    /// Syslog facility of the messages
+    #[arg(
+        long = "syslog-facility",
+        value_enum,
+        default_value = "daemon",
+        requires = "syslog",
+        required = false,
+    )]
+    syslog_facility: SyslogFacility,
+
+    /// Rules deciding each file's action: a built-in preset (classic) or a TOML rules file
+    #[arg(
+        long = "rules",
+        value_name = "PRESET|FILE",
+        required = false,
+    )]
+    rules: Option<String>,
+
+    /// Also pick up files in subdirectories of the directory when bundling
+    #[arg(
+        long = "recursive",
+        requires = "bundle",
+        required = false,
+    )]
+    recursive: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Archive, truncate and remove files in a directory
+    Rotate(RotateArgs),
+
+    /// Show what `rotate` would do without making any changes
+    Plan {
+        #[command(flatten)]
+        args: RotateArgs,
+
+        /// Print the plan as JSON
+        #[arg(
+            long = "json",
+            required = false,
+        )]
+        json: bool,
+    },
+
+    /// Show a directory's files, archives and most recent runs
+    Status {
+        /// Directory to report on
+        #[arg(short = 'd', long = "directory", value_name = "DIRECTORY")]
+        directory: String,
+
+        /// Number of recent runs to show
+        #[arg(long = "runs", value_name = "N", default_value = "5")]
+        runs: usize,
+    },
+
+    /// List a directory's archives grouped by log family, newest first
+    List {
+        /// Directory holding the archives
+        #[arg(short = 'd', long = "directory", value_name = "DIRECTORY")]
+        directory: String,
+    },
+
+    /// Show every rule checked against a file, and which one decided its action
+    Explain {
+        /// File to explain
+        file: PathBuf,
+
+        /// Number of days to keep archived files
+        #[arg(short = 'k', long = "keep-days", value_name = "DAYS", default_value = "7", allow_negative_numbers = true)]
+        keep_days: i64,
+
+        /// Rules to check: a built-in preset (classic) or a TOML rules file
+        #[arg(long = "rules", value_name = "PRESET|FILE", default_value = "classic")]
+        rules: String,
+    },
+
+    /// Print the lines matching a pattern in a directory's archives, decompressing them on the fly
+    Grep {
+        /// Regular expression to match lines against
+        pattern: String,
+
+        /// Directory holding the archives
+        #[arg(short = 'd', long = "directory", value_name = "DIRECTORY")]
+        directory: String,
+
+        /// Skip archives holding only lines from before this date (YYYY-MM-DD)
+        #[arg(long = "since", value_name = "DATE")]
+        since: Option<NaiveDate>,
+
+        /// Skip archives holding only lines from after this date (YYYY-MM-DD)
+        #[arg(long = "until", value_name = "DATE")]
+        until: Option<NaiveDate>,
+
+        /// Number of archives to search in parallel - defaults to the number of CPUs
+        #[arg(short = 'j', long = "jobs", value_name = "N")]
+        jobs: Option<usize>,
+    },
+
+    /// Extract the files of an archive back to where they were archived from
+    Restore {
+        /// Archive to restore (tar, tar.gz or zip)
+        archive: PathBuf,
+
+        /// Restore into this directory instead of where the files were archived from
+        #[arg(long = "to", value_name = "DIR")]
+        to: Option<PathBuf>,
+
+        /// Replace files that already exist
+        #[arg(long = "force")]
+        force: bool,
+    },
+
+    /// Re-check existing archives - every entry is decompressed and checksummed
+    Verify {
+        /// Archives to check (tar, tar.gz or zip)
+        #[arg(required = true)]
+        archives: Vec<PathBuf>,
+    },
+
+    /// Check a directory's manifest hash chain for deleted, modified or reordered archives
+    VerifyChain {
+        /// Directory holding the archives and their manifest
+        #[arg(value_name = "DIRECTORY")]
+        directory: String,
+    },
+
+    /// Validate a daemon policy file and show every target with its next run
+    CheckConfig {
+        /// TOML policy file with one [[target]] table per directory
+        #[arg(short = 'c', long = "config", value_name = "POLICY_FILE")]
+        config: PathBuf,
+    },
+
+    /// Run every target of a policy file on its own schedule until SIGTERM - SIGHUP reloads the policy
+    Daemon {
+        /// TOML policy file with one [[target]] table per directory
+        #[arg(short = 'c', long = "config", value_name = "POLICY_FILE")]
+        config: PathBuf,
+    },
+
+    /// Decrypt an encrypted (.age) archive with a private key
+    Decrypt {
+        /// Encrypted archive to decrypt
+        archive: PathBuf,
+
+        /// age identity file holding the private key(s)
+        #[arg(short = 'i', long = "identity", value_name = "IDENTITY_FILE")]
+        identity: PathBuf,
+
+        /// Where to write the decrypted archive - defaults to the archive path without .age
+        #[arg(short = 'o', long = "output", value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
 }
 
 #[cfg_attr(coverage_nightly, coverage(off))]
@@ -59,25 +471,180 @@ fn main() -> Result<()> {
     // E0599 No function or associated item 'parse' found in the current scope for struct Cli
     let args = <Cli as Parser>::parse();
 
-    let arg_directory = args.directory;
-    let arg_archive_method = args.archive_method;
-    let arg_keep_days = args.keep_days;
+    match args.command {
+        Some(Command::Rotate(rotate_args)) => rotate(rotate_args),
+        Some(Command::Plan { args: plan_args, json }) => plan(plan_args, json),
+        Some(Command::Status { directory, runs }) => status_details(&directory, runs),
+        Some(Command::List { directory }) => list_details(&directory),
+        Some(Command::Explain { file, keep_days, rules }) => explain_details(&file, keep_days, &RuleSet::load(&rules)?),
+        Some(Command::Grep { pattern, directory, since, until, jobs }) => {
+            let jobs = jobs.unwrap_or_else(|| std::thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1));
+            grep_details(&directory, &pattern, since, until, jobs)
+        }
+        Some(Command::Restore { archive, to, force }) => restore_details(&archive, &RestoreOptions { target_dir: to, force }),
+        Some(Command::Verify { archives }) => {
+            let failures = verify_existing_archives(&archives);
+            if failures > 0 {
+                return Err(anyhow::anyhow!("{} of {} archives failed verification", failures, archives.len()));
+            }
+            Ok(())
+        }
+        Some(Command::VerifyChain { directory }) => {
+            if !verify_chain_details(&directory)? {
+                return Err(anyhow::anyhow!("Manifest hash chain in '{}' is broken", directory));
+            }
+            Ok(())
+        }
+        Some(Command::CheckConfig { config }) => {
+            if !check_config_details(&config)? {
+                return Err(anyhow::anyhow!("Policy '{}' has target directories that do not exist", config.display()));
+            }
+            Ok(())
+        }
+        Some(Command::Daemon { config }) => run_daemon(&config),
+        Some(Command::Decrypt { archive, identity, output }) => decrypt_details(&archive, &identity, output.as_deref()),
+        None if args.dry_run => plan(args.rotate, false),
+        None => rotate(args.rotate),
+    }
+}
 
-    let file_list = gather_files_from_directory(&arg_directory)?;
-    
-    if args.dry_run {
-        println!("Dry Run with the following args...\n\
-         ARCHIVE METHOD: {:?}\n\
-         DIRECTORY PATH: {:?}\n\
-         KEEP FOR: {:?} DAYS", 
-                 arg_archive_method, arg_directory, arg_keep_days
-        );
-        
-        dry_run_details(file_list, arg_keep_days.into(), arg_archive_method);
+/// Run options for the rotation flags - syslog forwarding is only connected by `rotate`
+fn run_options(args: &RotateArgs) -> Result<RunOptions> {
+    let directory = args.directory.as_deref().expect("--directory is required");
+    Ok(RunOptions {
+        free_space_guard: args.min_free.map(|reserve| FreeSpaceGuard {
+            reserve,
+            on_low_space: args.on_low_space,
+        }),
+        verify: args.verify,
+        archive_mode: args.archive_mode,
+        create_mode: args.create_mode,
+        manifest: args.manifest,
+        recipients: args.recipients.clone(),
+        redactor: (args.redact || !args.redact_rules.is_empty()).then(|| {
+            let mut rules = args.redact_rules.clone();
+            if args.redact {
+                rules.extend(builtin_rules());
+            }
+            Redactor::new(rules)
+        }),
+        bundle: args.bundle.then(|| BundleOptions {
+            directory: PathBuf::from(directory),
+            group: args.bundle_group,
+        }),
+        append: args.append,
+        naming: args.naming.unwrap_or(if args.delay_compress { NamingScheme::Numbered } else { NamingScheme::Date }),
+        delay_compress: args.delay_compress,
+        jobs: args.jobs.into(),
+        parallel_compression: Some(ParallelCompression {
+            threshold: args.parallel_threshold,
+            threads: args.compress_threads
+                .map(usize::from)
+                .unwrap_or_else(|| std::thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1)),
+        }),
+        shutdown: None,
+        syslog: None,
+        rules: args.rules.as_deref().map(RuleSet::load).transpose()?.map(Arc::new),
+    })
+}
+
+/// Files a run looks at
+fn file_list(args: &RotateArgs, directory: &str) -> Result<Vec<PathBuf>> {
+    let mut file_list = if args.recursive {
+        gather_files_recursively(directory)?
     }
     else {
-        actual_run(file_list, arg_keep_days.into(), arg_archive_method);
+        gather_files_from_directory(directory)?
+    };
+    if args.count_only {
+        // Archives are left to the keep-count pass
+        file_list.retain(|file| file.to_str().and_then(split_archive_name).is_none());
     }
+    Ok(file_list)
+}
 
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn rotate(args: RotateArgs) -> Result<()> {
+    // Both are required by clap for `rotate` and the flags without a subcommand
+    let arg_directory = args.directory.clone().expect("--directory is required");
+    let arg_archive_method = args.archive_method.clone().expect("--archive-method is required");
+    let mut run_options = run_options(&args)?;
+    if args.syslog {
+        run_options.syslog = Some(Arc::new(SyslogSender::connect(&args.syslog_endpoint, args.syslog_facility)?));
+    }
+
+    for stray_file in cleanup_stray_temp_files(&arg_directory)? {
+        println!("Removed stray temp file from an interrupted run: {}", stray_file.display());
+    }
+
+    let started = Local::now();
+    let mut report = actual_run(file_list(&args, &arg_directory)?, args.keep_days.into(), arg_archive_method.clone(), &run_options);
+    print_report(&report);
+
+    if let Some(keep_count) = args.keep_count {
+        let removed = apply_count_retention(&arg_directory, keep_count, &run_options, false)?;
+        print_report(&removed);
+        report.extend(removed);
+    }
+
+    if let Some(max_total_size) = args.max_total_size {
+        let budget_report = apply_disk_budget(&arg_directory, max_total_size, args.force_rotate, arg_archive_method, &run_options, false)?;
+        print_report(&budget_report);
+        report.extend(budget_report);
+    }
+
+    record_run(Path::new(&arg_directory), RunSummary::from_report(started, Local::now(), &report))
+}
+
+#[cfg_attr(coverage_nightly, coverage(off))]
+fn plan(args: RotateArgs, json: bool) -> Result<()> {
+    let arg_directory = args.directory.clone().expect("--directory is required");
+    let arg_archive_method = args.archive_method.clone().expect("--archive-method is required");
+    let arg_keep_days = args.keep_days;
+    let run_options = run_options(&args)?;
+    let file_list = file_list(&args, &arg_directory)?;
+
+    if json {
+        let mut actions = plan_run(&file_list, arg_keep_days.into(), &arg_archive_method, &run_options);
+        if let Some(keep_count) = args.keep_count {
+            actions.extend(plan_count_retention(&arg_directory, keep_count)?);
+        }
+        let mut disk_budget = serde_json::Value::Null;
+        if let Some(max_total_size) = args.max_total_size {
+            let (budget, budget_actions) = plan_disk_budget_actions(&arg_directory, max_total_size, args.force_rotate, &arg_archive_method, &run_options)?;
+            actions.extend(budget_actions);
+            disk_budget = serde_json::json!({
+                "max_total_size": max_total_size,
+                "usage_before": budget.usage_before,
+                "usage_after": budget.usage_after,
+            });
+        }
+        let plan = serde_json::json!({
+            "directory": arg_directory,
+            "archive_method": arg_archive_method.to_possible_value().map(|value| value.get_name().to_string()),
+            "keep_days": arg_keep_days,
+            "keep_count": args.keep_count,
+            "actions": actions,
+            "disk_budget": disk_budget,
+        });
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+        return Ok(());
+    }
+
+    println!("Dry Run with the following args...\n\
+     ARCHIVE METHOD: {:?}\n\
+     DIRECTORY PATH: {:?}\n\
+     KEEP FOR: {:?} DAYS", 
+             arg_archive_method, arg_directory, arg_keep_days
+    );
+    dry_run_details(file_list, arg_keep_days.into(), arg_archive_method.clone(), &run_options);
+
+    if let Some(keep_count) = args.keep_count {
+        apply_count_retention(&arg_directory, keep_count, &run_options, true)?;
+    }
+
+    if let Some(max_total_size) = args.max_total_size {
+        apply_disk_budget(&arg_directory, max_total_size, args.force_rotate, arg_archive_method, &run_options, true)?;
+    }
     Ok(())
 }
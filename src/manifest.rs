@@ -0,0 +1,274 @@
+//! Per-directory checksum manifest
+//!
+//! Every archive written (and every archive removed by retention) appends one line to `.logrotate-manifest`
+//! in the archive's directory. Each line carries the hash of the line before it, so deleting, editing or
+//! reordering lines - or touching the archives they describe - breaks the chain and shows up in `verify-chain`
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+
+use crate::{file_digest, gather_files_from_directory, split_archive_name};
+
+/// Name of the manifest file kept in each archive directory
+pub const MANIFEST_FILE_NAME: &str = ".logrotate-manifest";
+
+/// Held while an entry is read, chained and appended
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// `prev_hash` of the first entry in a chain
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// What happened to the archive an entry describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestEvent {
+    Archive,
+    Remove,
+}
+
+impl fmt::Display for ManifestEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ManifestEvent::Archive => "archive",
+            ManifestEvent::Remove => "remove",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for ManifestEvent {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "archive" => Ok(ManifestEvent::Archive),
+            "remove" => Ok(ManifestEvent::Remove),
+            _ => Err(anyhow::anyhow!("Unknown manifest event '{}'", s)),
+        }
+    }
+}
+
+/// One line of the manifest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub seq: u64,
+    /// RFC 3339, UTC
+    pub timestamp: String,
+    pub event: ManifestEvent,
+    /// Archive file name, relative to the manifest's directory
+    pub archive: String,
+    /// SHA-256 of the archive file - when it was written, or right before it was removed
+    pub archive_sha256: String,
+    /// SHA-256 of the original content that went into the archive, `-` for removals
+    pub source_sha256: String,
+    pub prev_hash: String,
+    pub entry_hash: String,
+}
+
+impl ManifestEntry {
+    /// Hash over every field but `entry_hash` itself
+    pub fn compute_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.seq, self.timestamp, self.event, self.archive, self.archive_sha256, self.source_sha256, self.prev_hash,
+        ));
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+impl fmt::Display for ManifestEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.seq, self.timestamp, self.event, self.archive, self.archive_sha256, self.source_sha256, self.prev_hash, self.entry_hash,
+        )
+    }
+}
+
+impl FromStr for ManifestEntry {
+    type Err = anyhow::Error;
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [seq, timestamp, event, archive, archive_sha256, source_sha256, prev_hash, entry_hash] = fields.as_slice() else {
+            return Err(anyhow::anyhow!("Malformed manifest line: expected 8 fields, found {}", fields.len()));
+        };
+
+        Ok(ManifestEntry {
+            seq: seq.parse()?,
+            timestamp: timestamp.to_string(),
+            event: event.parse()?,
+            archive: archive.to_string(),
+            archive_sha256: archive_sha256.to_string(),
+            source_sha256: source_sha256.to_string(),
+            prev_hash: prev_hash.to_string(),
+            entry_hash: entry_hash.to_string(),
+        })
+    }
+}
+
+/// Manifest file of a provided directory
+pub fn manifest_path(dir_path: &Path) -> PathBuf {
+    dir_path.join(MANIFEST_FILE_NAME)
+}
+
+/// Directory holding a provided archive, `.` for bare file names
+fn archive_directory(archive_path: &Path) -> &Path {
+    archive_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Read every entry of a directory's manifest - empty when there is no manifest yet
+pub fn read_manifest(dir_path: &Path) -> anyhow::Result<Vec<ManifestEntry>> {
+    let manifest = match fs::read_to_string(manifest_path(dir_path)) {
+        Ok(manifest) => manifest,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error.into()),
+    };
+
+    manifest
+        .lines()
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            line.parse()
+                .map_err(|error: anyhow::Error| error.context(format!("Manifest line {}", index + 1)))
+        })
+        .collect()
+}
+
+/// Append an entry for a provided archive to the manifest in its directory, chained onto the last entry
+/// Appends are serialised - with several worker threads, two entries chained onto the same last entry would fork the chain
+pub fn append_manifest_entry(archive_path: &Path, event: ManifestEvent, archive_sha256: &str, source_sha256: &str) -> anyhow::Result<ManifestEntry> {
+    let _guard = MANIFEST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let dir_path = archive_directory(archive_path);
+    let last_entry = read_manifest(dir_path)?.pop();
+
+    let mut entry = ManifestEntry {
+        seq: last_entry.as_ref().map(|entry| entry.seq + 1).unwrap_or(1),
+        timestamp: Utc::now().to_rfc3339(),
+        event,
+        archive: archive_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        archive_sha256: archive_sha256.to_string(),
+        source_sha256: source_sha256.to_string(),
+        prev_hash: last_entry
+            .map(|entry| entry.entry_hash)
+            .unwrap_or_else(|| GENESIS_HASH.to_string()),
+        entry_hash: String::new(),
+    };
+    entry.entry_hash = entry.compute_hash();
+
+    let mut manifest = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(manifest_path(dir_path))?;
+    writeln!(manifest, "{}", entry)?;
+    manifest.sync_all()?;
+
+    Ok(entry)
+}
+
+/// Record a freshly written archive
+pub fn record_archive(archive_path: &Path, source_sha256: &str) -> anyhow::Result<ManifestEntry> {
+    let (_, archive_sha256) = file_digest(archive_path)?;
+    append_manifest_entry(archive_path, ManifestEvent::Archive, &archive_sha256, source_sha256)
+}
+
+/// Hash an archive, remove it and record the removal - so retention does not read as tampering
+pub fn remove_archive_recorded(archive_path: &Path) -> anyhow::Result<ManifestEntry> {
+    let (_, archive_sha256) = file_digest(archive_path)?;
+    fs::remove_file(archive_path)?;
+    append_manifest_entry(archive_path, ManifestEvent::Remove, &archive_sha256, "-")
+}
+
+/// Record an archive renamed in place, e.g. `app.log.1.tar.gz` shifted to `app.log.2.tar.gz` by numbered rotation -
+/// a removal of the old name and an archive entry for the new one, carrying over the recorded source checksum
+pub fn record_rename(old_path: &Path, new_path: &Path) -> anyhow::Result<ManifestEntry> {
+    let old_name = old_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let source_sha256 = read_manifest(archive_directory(new_path))?
+        .into_iter()
+        .rev()
+        .find(|entry| entry.archive == old_name)
+        .filter(|entry| entry.event == ManifestEvent::Archive)
+        .map(|entry| entry.source_sha256)
+        .unwrap_or_else(|| "-".to_string());
+
+    let (_, archive_sha256) = file_digest(new_path)?;
+    append_manifest_entry(old_path, ManifestEvent::Remove, &archive_sha256, "-")?;
+    append_manifest_entry(new_path, ManifestEvent::Archive, &archive_sha256, &source_sha256)
+}
+
+/// Outcome of checking a directory's manifest chain
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainReport {
+    pub entries: usize,
+    /// Broken links, edited entries and deleted or modified archives
+    pub problems: Vec<String>,
+    /// Archives in the directory the manifest knows nothing about
+    pub untracked: Vec<PathBuf>,
+}
+
+impl ChainReport {
+    pub fn is_intact(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Walk a directory's manifest: every entry must hash to its `entry_hash`, link to the previous entry and carry
+/// the next sequence number, and every archive the chain still considers live must exist with the recorded hash
+pub fn verify_chain(dir_path: &Path) -> anyhow::Result<ChainReport> {
+    let entries = read_manifest(dir_path)?;
+    let mut report = ChainReport { entries: entries.len(), ..Default::default() };
+
+    let mut prev_hash = GENESIS_HASH.to_string();
+    let mut live_archives: BTreeMap<String, String> = BTreeMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let expected_seq = index as u64 + 1;
+        if entry.seq != expected_seq {
+            report.problems.push(format!("Entry {} has sequence number {} - entries were removed or reordered", expected_seq, entry.seq));
+        }
+        if entry.prev_hash != prev_hash {
+            report.problems.push(format!("Entry {} does not link to the entry before it", entry.seq));
+        }
+        if entry.compute_hash() != entry.entry_hash {
+            report.problems.push(format!("Entry {} ({}) was modified", entry.seq, entry.archive));
+        }
+        prev_hash = entry.entry_hash.clone();
+
+        match entry.event {
+            ManifestEvent::Archive => live_archives.insert(entry.archive.clone(), entry.archive_sha256.clone()),
+            ManifestEvent::Remove => live_archives.remove(&entry.archive),
+        };
+    }
+
+    for (archive, recorded_sha256) in &live_archives {
+        let archive_path = dir_path.join(archive);
+        match file_digest(&archive_path) {
+            Ok((_, sha256)) if &sha256 == recorded_sha256 => {}
+            Ok(_) => report.problems.push(format!("Archive {} was modified since it was recorded", archive)),
+            Err(_) => report.problems.push(format!("Archive {} was deleted without a removal entry", archive)),
+        }
+    }
+
+    for file in gather_files_from_directory(&dir_path.to_string_lossy())? {
+        let Some(file_str) = file.to_str() else { continue };
+        let file_name = file.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        if split_archive_name(file_str).is_some() && !live_archives.contains_key(&file_name) {
+            report.untracked.push(file);
+        }
+    }
+
+    Ok(report)
+}
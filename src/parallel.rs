@@ -0,0 +1,163 @@
+//! Block-parallel compression for large inputs
+//!
+//! Above the configured size, gzip output is cut into independently compressed members - as pigz does - which
+//! every gzip reader (`zcat`, `gunzip`, `MultiGzDecoder`) concatenates back. zstd and xz use their own
+//! multithreaded encoders
+
+use std::io::{self, Write};
+
+use flate2::Compression;
+use flate2::GzBuilder;
+use flate2::write::GzEncoder;
+
+use crate::run_jobs;
+
+/// Uncompressed bytes per gzip member
+pub const GZIP_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+/// When and how wide to compress in parallel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelCompression {
+    /// Inputs of at least this many bytes are compressed in parallel
+    pub threshold: u64,
+    pub threads: usize,
+}
+
+impl ParallelCompression {
+    /// Threads to compress an input of `size` bytes with - None when it stays on one core
+    pub fn threads_for(&self, size: u64) -> Option<usize> {
+        (size >= self.threshold && self.threads > 1).then_some(self.threads)
+    }
+}
+
+/// Gzip writer compressing `threads` blocks at a time, each into its own gzip member
+/// Only the first member carries the file name and mtime
+pub struct ParallelGzEncoder<W: Write> {
+    inner: W,
+    filename: Option<Vec<u8>>,
+    mtime: u32,
+    threads: usize,
+    buffer: Vec<u8>,
+    pending: Vec<Vec<u8>>,
+    members_written: usize,
+}
+
+impl<W: Write> ParallelGzEncoder<W> {
+    pub fn new(inner: W, filename: Option<&[u8]>, mtime: u32, threads: usize) -> ParallelGzEncoder<W> {
+        ParallelGzEncoder {
+            inner,
+            filename: filename.map(|filename| filename.to_vec()),
+            mtime,
+            threads: threads.max(1),
+            buffer: Vec::with_capacity(GZIP_BLOCK_SIZE),
+            pending: Vec::new(),
+            members_written: 0,
+        }
+    }
+
+    /// Compress every pending block in parallel and write the members out in order
+    fn compress_pending(&mut self) -> io::Result<()> {
+        let blocks: Vec<(bool, Vec<u8>)> = self
+            .pending
+            .drain(..)
+            .enumerate()
+            .map(|(index, block)| (self.members_written + index == 0, block))
+            .collect();
+
+        let members = run_jobs(&blocks, self.threads, |(first, block)| {
+            let mut builder = GzBuilder::new();
+            if *first {
+                builder = builder.mtime(self.mtime);
+                if let Some(filename) = &self.filename {
+                    builder = builder.filename(filename.as_slice());
+                }
+            }
+            let mut encoder = builder.write(Vec::with_capacity(block.len() / 2), Compression::default());
+            encoder.write_all(block)?;
+            encoder.finish()
+        });
+
+        for member in members {
+            self.inner.write_all(&member?)?;
+            self.members_written += 1;
+        }
+        Ok(())
+    }
+
+    /// Compress what is left and hand the inner writer back - an empty input still gets one (empty) member
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.buffer.is_empty() || self.members_written + self.pending.len() == 0 {
+            let block = std::mem::take(&mut self.buffer);
+            self.pending.push(block);
+        }
+        self.compress_pending()?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ParallelGzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = buf.len().min(GZIP_BLOCK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+
+        if self.buffer.len() == GZIP_BLOCK_SIZE {
+            let block = std::mem::replace(&mut self.buffer, Vec::with_capacity(GZIP_BLOCK_SIZE));
+            self.pending.push(block);
+            if self.pending.len() == self.threads {
+                self.compress_pending()?;
+            }
+        }
+        Ok(length)
+    }
+
+    /// Members can only be written once their block is full - pending input stays buffered
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Gzip writer for an input of known size - one stream on a single core, or parallel members
+pub enum GzipWriter<W: Write> {
+    Single(GzEncoder<W>),
+    Parallel(ParallelGzEncoder<W>),
+}
+
+impl<W: Write> GzipWriter<W> {
+    /// `threads` comes from `ParallelCompression::threads_for` - None for a regular single stream
+    pub fn new(inner: W, filename: Option<&[u8]>, mtime: u32, threads: Option<usize>) -> GzipWriter<W> {
+        match threads {
+            Some(threads) => GzipWriter::Parallel(ParallelGzEncoder::new(inner, filename, mtime, threads)),
+            None => {
+                let mut builder = GzBuilder::new().mtime(mtime);
+                if let Some(filename) = filename {
+                    builder = builder.filename(filename);
+                }
+                GzipWriter::Single(builder.write(inner, Compression::default()))
+            }
+        }
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            GzipWriter::Single(encoder) => encoder.finish(),
+            GzipWriter::Parallel(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            GzipWriter::Single(encoder) => encoder.write(buf),
+            GzipWriter::Parallel(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            GzipWriter::Single(encoder) => encoder.flush(),
+            GzipWriter::Parallel(encoder) => encoder.flush(),
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Policy file for daemon mode
+//!
+//! A TOML file with one `[[target]]` table per directory. Every target carries its own schedule and the same
+//! settings as the command line flags, written with the same values, e.g.
+//!
+//! ```toml
+//! [[target]]
+//! name = "nginx"
+//! directory = "/var/log/nginx"
+//! schedule = "daily at 03:00"
+//! archive_method = "tar-gunzip"
+//! keep_days = 14
+//! keep_count = 10
+//! max_size = "500M"
+//! ```
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::bundle::{BundleGroup, BundleOptions};
+use crate::crypto::parse_recipient;
+use crate::parallel::ParallelCompression;
+use crate::redact::{RedactionRule, Redactor, builtin_rules};
+use crate::rules::RuleSet;
+use crate::schedule::{Schedule, parse_interval};
+use crate::syslog::{DEFAULT_SYSLOG_SOCKET, SyslogEndpoint, SyslogFacility, SyslogSender};
+use crate::{ArchiveType, FreeSpaceGuard, FreeSpaceReserve, LowSpaceAction, NamingScheme, RunOptions, parse_mode, parse_size};
+
+/// Policy file as written
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyFile {
+    #[serde(default, rename = "target")]
+    pub targets: Vec<TargetConfig>,
+}
+
+/// One `[[target]]` table as written - see the command line flags for what each setting does
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TargetConfig {
+    pub name: String,
+    pub directory: String,
+    pub schedule: String,
+    pub archive_method: String,
+    #[serde(default = "default_keep_days")]
+    pub keep_days: u8,
+    pub keep_count: Option<usize>,
+    #[serde(default)]
+    pub count_only: bool,
+    pub max_total_size: Option<String>,
+    #[serde(default)]
+    pub force_rotate: bool,
+    pub min_free: Option<String>,
+    pub on_low_space: Option<String>,
+    #[serde(default)]
+    pub verify: bool,
+    pub archive_mode: Option<String>,
+    pub create_mode: Option<String>,
+    #[serde(default)]
+    pub manifest: bool,
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    #[serde(default)]
+    pub redact: bool,
+    #[serde(default)]
+    pub redact_rules: Vec<String>,
+    #[serde(default)]
+    pub bundle: bool,
+    pub bundle_group: Option<String>,
+    #[serde(default)]
+    pub recursive: bool,
+    #[serde(default)]
+    pub append: bool,
+    pub naming: Option<String>,
+    #[serde(default)]
+    pub delay_compress: bool,
+    pub jobs: Option<usize>,
+    pub parallel_threshold: Option<String>,
+    pub compress_threads: Option<usize>,
+    pub max_size: Option<String>,
+    pub rotate_cooldown: Option<String>,
+    #[serde(default)]
+    pub syslog: bool,
+    pub syslog_endpoint: Option<String>,
+    pub syslog_facility: Option<String>,
+    /// Built-in preset name or rules file
+    pub rules: Option<String>,
+}
+
+fn default_keep_days() -> u8 {
+    7
+}
+
+/// A validated target, ready to run
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub name: String,
+    pub directory: String,
+    pub schedule: Schedule,
+    pub archive_type: ArchiveType,
+    pub keep_days: u8,
+    pub keep_count: Option<usize>,
+    pub count_only: bool,
+    pub max_total_size: Option<u64>,
+    pub force_rotate: bool,
+    pub recursive: bool,
+    /// Rotate a file as soon as it grows past this size - no size watch when None
+    pub max_size: Option<u64>,
+    /// Shortest time between two size triggered rotations of the same file
+    pub rotate_cooldown: Duration,
+    /// Syslog destination - validated when the policy is parsed, connected by `connect_syslog`
+    pub syslog: Option<(SyslogEndpoint, SyslogFacility)>,
+    pub options: RunOptions,
+}
+
+impl Target {
+    /// Open the syslog connection of the target into its run options - a no-op without syslog forwarding
+    pub fn connect_syslog(&mut self) -> anyhow::Result<()> {
+        if let Some((endpoint, facility)) = &self.syslog {
+            self.options.syslog = Some(Arc::new(SyslogSender::connect(endpoint, *facility)?));
+        }
+        Ok(())
+    }
+}
+
+/// Parse a command line style enum value, e.g. `tar-gunzip`
+fn parse_value<T: ValueEnum>(setting: &str, value: &str) -> anyhow::Result<T> {
+    T::from_str(value, true).map_err(|_| {
+        let possible: Vec<String> = T::value_variants()
+            .iter()
+            .filter_map(|variant| variant.to_possible_value().map(|value| value.get_name().to_string()))
+            .collect();
+        anyhow::anyhow!("Invalid {} '{}' - possible values: {}", setting, value, possible.join(", "))
+    })
+}
+
+impl TargetConfig {
+    /// Check every setting and turn the target into its run options
+    pub fn resolve(&self) -> anyhow::Result<Target> {
+        let schedule: Schedule = self.schedule.parse()?;
+        // Size triggered rotations and schedules firing several times a day rotate more than once a day - a second
+        // run would write the emptied log over the day's dated archive
+        let several_per_day = self.max_size.is_some() || schedule.fires_more_than_daily();
+        let naming = match &self.naming {
+            Some(naming) => parse_value("naming", naming)?,
+            None if self.delay_compress || (several_per_day && !self.append) => NamingScheme::Numbered,
+            None => NamingScheme::Date,
+        };
+        if several_per_day && !self.append && (naming == NamingScheme::Date || self.bundle) {
+            let reason = if self.max_size.is_some() { "max_size" } else { "A schedule running more than once a day" };
+            return Err(anyhow::anyhow!("{} with date naming or bundle needs append", reason));
+        }
+        if self.verify && !self.recipients.is_empty() {
+            return Err(anyhow::anyhow!("verify cannot be combined with recipients"));
+        }
+        if self.count_only && self.keep_count.is_none() {
+            return Err(anyhow::anyhow!("count_only needs keep_count"));
+        }
+        // The same combinations archive_file and bundles refuse at run time, caught when the policy loads
+        let archive_type: ArchiveType = parse_value("archive_method", &self.archive_method)?;
+        if self.append && !self.recipients.is_empty() {
+            return Err(anyhow::anyhow!("append cannot be combined with recipients"));
+        }
+        if (self.append || self.bundle) && archive_type.is_single_file() {
+            return Err(anyhow::anyhow!("append and bundle need a tar, tar-gunzip or zip archive_method"));
+        }
+        if self.append && naming == NamingScheme::Numbered {
+            return Err(anyhow::anyhow!("append needs date naming"));
+        }
+        if self.delay_compress && (self.bundle || !self.recipients.is_empty() || naming != NamingScheme::Numbered) {
+            return Err(anyhow::anyhow!("delay_compress needs numbered naming and cannot be combined with bundle or recipients"));
+        }
+
+        let mut redaction_rules = self
+            .redact_rules
+            .iter()
+            .map(|rule| RedactionRule::parse(rule))
+            .collect::<anyhow::Result<Vec<RedactionRule>>>()?;
+        if self.redact {
+            redaction_rules.extend(builtin_rules());
+        }
+
+        let options = RunOptions {
+            free_space_guard: match &self.min_free {
+                Some(reserve) => Some(FreeSpaceGuard {
+                    reserve: reserve.parse::<FreeSpaceReserve>()?,
+                    on_low_space: match &self.on_low_space {
+                        Some(action) => parse_value("on_low_space", action)?,
+                        None => LowSpaceAction::Skip,
+                    },
+                }),
+                None => None,
+            },
+            verify: self.verify,
+            archive_mode: self.archive_mode.as_deref().map(parse_mode).transpose()?,
+            create_mode: self.create_mode.as_deref().map(parse_mode).transpose()?,
+            manifest: self.manifest,
+            recipients: self
+                .recipients
+                .iter()
+                .map(|recipient| parse_recipient(recipient))
+                .collect::<anyhow::Result<Vec<age::x25519::Recipient>>>()?,
+            redactor: (!redaction_rules.is_empty()).then(|| Redactor::new(redaction_rules)),
+            bundle: match self.bundle {
+                true => Some(BundleOptions {
+                    directory: self.directory.clone().into(),
+                    group: match &self.bundle_group {
+                        Some(group) => parse_value("bundle_group", group)?,
+                        None => BundleGroup::None,
+                    },
+                }),
+                false => None,
+            },
+            append: self.append,
+            naming,
+            delay_compress: self.delay_compress,
+            jobs: self.jobs.unwrap_or(1),
+            parallel_compression: Some(ParallelCompression {
+                threshold: parse_size(self.parallel_threshold.as_deref().unwrap_or("256M"))?,
+                threads: self
+                    .compress_threads
+                    .unwrap_or_else(|| std::thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1)),
+            }),
+            rules: self.rules.as_deref().map(RuleSet::load).transpose()?.map(Arc::new),
+            ..Default::default()
+        };
+
+        Ok(Target {
+            name: self.name.clone(),
+            directory: self.directory.clone(),
+            schedule,
+            archive_type,
+            keep_days: self.keep_days,
+            keep_count: self.keep_count,
+            count_only: self.count_only,
+            max_total_size: self.max_total_size.as_deref().map(parse_size).transpose()?,
+            force_rotate: self.force_rotate,
+            recursive: self.recursive,
+            max_size: self.max_size.as_deref().map(parse_size).transpose()?,
+            rotate_cooldown: parse_interval(self.rotate_cooldown.as_deref().unwrap_or("1m"))?,
+            syslog: match self.syslog {
+                true => {
+                    let endpoint: SyslogEndpoint = self.syslog_endpoint.as_deref().unwrap_or(DEFAULT_SYSLOG_SOCKET).parse()?;
+                    let facility = match &self.syslog_facility {
+                        Some(facility) => parse_value("syslog_facility", facility)?,
+                        None => SyslogFacility::Daemon,
+                    };
+                    Some((endpoint, facility))
+                }
+                false => None,
+            },
+            options,
+        })
+    }
+}
+
+/// Parse and validate a policy - every target has to be valid, so a broken edit never half applies
+pub fn parse_policy(policy: &str) -> anyhow::Result<Vec<Target>> {
+    let policy_file: PolicyFile = toml::from_str(policy)?;
+    if policy_file.targets.is_empty() {
+        return Err(anyhow::anyhow!("Policy has no [[target]] tables"));
+    }
+
+    let mut targets: Vec<Target> = Vec::new();
+    for target_config in &policy_file.targets {
+        if targets.iter().any(|target| target.name == target_config.name) {
+            return Err(anyhow::anyhow!("Target name '{}' is used more than once", target_config.name));
+        }
+        let target = target_config
+            .resolve()
+            .map_err(|error| error.context(format!("Target '{}'", target_config.name)))?;
+        targets.push(target);
+    }
+    Ok(targets)
+}
+
+/// Read, parse and validate a policy file - nothing is connected, see `load_policy_connected`
+pub fn load_policy(policy_path: &Path) -> anyhow::Result<Vec<Target>> {
+    let policy = fs::read_to_string(policy_path)
+        .map_err(|error| anyhow::anyhow!("Unable to read policy '{}': {}", policy_path.display(), error))?;
+    parse_policy(&policy).map_err(|error| error.context(format!("Invalid policy '{}'", policy_path.display())))
+}
+
+/// Read a policy file and connect the syslog forwarding of its targets, ready to run
+pub fn load_policy_connected(policy_path: &Path) -> anyhow::Result<Vec<Target>> {
+    let mut targets = load_policy(policy_path)?;
+    for target in targets.iter_mut() {
+        target
+            .connect_syslog()
+            .map_err(|error| error.context(format!("Target '{}'", target.name)))?;
+    }
+    Ok(targets)
+}
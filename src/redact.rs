@@ -0,0 +1,177 @@
+//! Redaction of secrets and PII while logs are streamed into an archive
+//!
+//! Rules are applied line by line, in order, to the bytes going into the tar or zip entry. The live file is
+//! truncated as usual afterwards, so the archive is the only copy left - and it is the sanitized one
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufRead, Read};
+
+use regex::bytes::{Captures, Regex};
+
+/// Number of redactions per rule name
+pub type RedactionCounts = BTreeMap<String, u64>;
+
+/// A named pattern whose matches get replaced with `[REDACTED:<name>]`
+#[derive(Debug, Clone)]
+pub struct RedactionRule {
+    pub name: String,
+    pub pattern: Regex,
+    /// Only redact matches passing the Luhn check - keeps order ids and timestamps out of the card detector
+    pub luhn_check: bool,
+}
+
+impl RedactionRule {
+    pub fn new(name: &str, pattern: &str) -> anyhow::Result<RedactionRule> {
+        Ok(RedactionRule {
+            name: name.to_string(),
+            pattern: Regex::new(pattern)
+                .map_err(|error| anyhow::anyhow!("Invalid pattern for redaction rule '{}': {}", name, error))?,
+            luhn_check: false,
+        })
+    }
+
+    /// Parse a `NAME=REGEX` rule from the command line
+    pub fn parse(rule: &str) -> anyhow::Result<RedactionRule> {
+        let (name, pattern) = rule
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Redaction rule '{}' is not of the form NAME=REGEX", rule))?;
+        if name.trim().is_empty() {
+            return Err(anyhow::anyhow!("Redaction rule '{}' has an empty name", rule));
+        }
+        RedactionRule::new(name.trim(), pattern)
+    }
+}
+
+/// Built-in detectors: bearer / key=value tokens, AWS access keys, emails, IPv4 and IPv6 addresses, card numbers
+pub fn builtin_rules() -> Vec<RedactionRule> {
+    let rules = [
+        ("token", r"(?i)\bbearer\s+[A-Za-z0-9\-._~+/]+=*|(?i)\b(?:api[_-]?key|access[_-]?token|token|secret|passw(?:or)?d)\s*[:=]\s*[^\s,;&]+"),
+        ("aws-key", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+        ("email", r"(?i)\b[a-z0-9._%+\-]+@[a-z0-9.\-]+\.[a-z]{2,}\b"),
+        ("ipv4", r"\b(?:(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.){3}(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\b"),
+        ("ipv6", r"(?i)\b(?:[0-9a-f]{1,4}:){7}[0-9a-f]{1,4}\b|(?i)\b(?:[0-9a-f]{1,4}:){1,7}:(?:[0-9a-f]{1,4}(?::[0-9a-f]{1,4}){0,6})?\b"),
+        // 4-4-4-4 and 4-6-5 groupings, separated by spaces, dashes or nothing
+        ("credit-card", r"\b\d{4}[ \-]?\d{4,6}[ \-]?\d{4,5}(?:[ \-]?\d{1,4})?\b"),
+    ];
+
+    rules
+        .iter()
+        .map(|(name, pattern)| {
+            let mut rule = RedactionRule::new(name, pattern).expect("built-in redaction patterns are valid");
+            rule.luhn_check = *name == "credit-card";
+            rule
+        })
+        .collect()
+}
+
+/// Luhn checksum over the digits of a candidate card number
+pub fn luhn_valid(candidate: &[u8]) -> bool {
+    let digits: Vec<u32> = candidate
+        .iter()
+        .filter(|byte| byte.is_ascii_digit())
+        .map(|byte| (byte - b'0') as u32)
+        .collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, digit)| {
+            if index % 2 == 1 {
+                let doubled = digit * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                *digit
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Ordered set of redaction rules
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    pub rules: Vec<RedactionRule>,
+}
+
+impl Redactor {
+    pub fn new(rules: Vec<RedactionRule>) -> Redactor {
+        Redactor { rules }
+    }
+
+    /// Apply every rule to one line, adding to the per-rule counts
+    pub fn redact_line(&self, line: &[u8], counts: &mut RedactionCounts) -> Vec<u8> {
+        let mut redacted = line.to_vec();
+        for rule in &self.rules {
+            let mut matches = 0;
+            let replacement = format!("[REDACTED:{}]", rule.name);
+            redacted = rule
+                .pattern
+                .replace_all(&redacted, |captures: &Captures| {
+                    let found = captures.get(0).map(|found| found.as_bytes()).unwrap_or_default();
+                    if rule.luhn_check && !luhn_valid(found) {
+                        return found.to_vec();
+                    }
+                    matches += 1;
+                    replacement.clone().into_bytes()
+                })
+                .into_owned();
+
+            if matches > 0 {
+                *counts.entry(rule.name.clone()).or_default() += matches;
+            }
+        }
+        redacted
+    }
+}
+
+/// Reader yielding the redacted form of another reader, one line at a time
+pub struct RedactingReader<'a, R: BufRead> {
+    inner: R,
+    redactor: &'a Redactor,
+    line: Vec<u8>,
+    position: usize,
+    pub counts: RedactionCounts,
+}
+
+impl<'a, R: BufRead> RedactingReader<'a, R> {
+    pub fn new(inner: R, redactor: &'a Redactor) -> RedactingReader<'a, R> {
+        RedactingReader { inner, redactor, line: Vec::new(), position: 0, counts: RedactionCounts::new() }
+    }
+}
+
+impl<R: BufRead> Read for RedactingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.line.len() {
+            let mut raw_line = Vec::new();
+            if self.inner.read_until(b'\n', &mut raw_line)? == 0 {
+                return Ok(0);
+            }
+            self.line = self.redactor.redact_line(&raw_line, &mut self.counts);
+            self.position = 0;
+        }
+
+        let available = &self.line[self.position..];
+        let length = available.len().min(buf.len());
+        buf[..length].copy_from_slice(&available[..length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// Render redaction counts for the run report, e.g. `email=3, ipv4=1`
+pub struct DisplayCounts<'a>(pub &'a RedactionCounts);
+
+impl fmt::Display for DisplayCounts<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rendered: Vec<String> = self.0
+            .iter()
+            .map(|(name, count)| format!("{}={}", name, count))
+            .collect();
+        write!(f, "{}", rendered.join(", "))
+    }
+}
@@ -0,0 +1,146 @@
+//! Restore archived files back into place
+//!
+//! Entry names carry the original file name - tar entries and relative bundle entries are relative to the archive's
+//! directory (bundles keep their subdirectories), other zip entries hold the path the file was archived from, of which
+//! only the file name is used. Restored files get the mode and mtime stored with their entry
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{Local, NaiveDate, TimeZone};
+
+use crate::bundle::BUNDLE_PREFIX;
+use crate::{ArchiveType, write_atomically};
+
+/// Where and how to restore
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    /// Directory to restore into instead of the original location
+    pub target_dir: Option<PathBuf>,
+    /// Replace files that already exist
+    pub force: bool,
+}
+
+/// Where an entry goes back to
+pub fn restore_destination(archive_path: &Path, entry_name: &str, archive_type: &ArchiveType, options: &RestoreOptions) -> anyhow::Result<PathBuf> {
+    let entry_path = Path::new(entry_name);
+    if entry_path.components().any(|component| component == Component::ParentDir) || entry_name.is_empty() {
+        return Err(anyhow::anyhow!("Refusing to restore entry '{}' - it points outside of its directory", entry_name));
+    }
+    // Bundle entries are relative to the bundle's directory, like tar entries
+    let bundle_entry = archive_path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(BUNDLE_PREFIX))
+        && entry_path.components().all(|component| matches!(component, Component::Normal(_)));
+    if matches!(archive_type, ArchiveType::Zip) && !bundle_entry {
+        // Other zip entries hold the full original path, which is never trusted - only the file name is restored, next
+        // to the archive (where the archived file lived) or into the target directory
+        let file_name = entry_path
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Refusing to restore entry '{}' - it has no file name", entry_name))?;
+        return match &options.target_dir {
+            Some(target_dir) => Ok(target_dir.join(file_name)),
+            None => Ok(archive_directory(archive_path).join(file_name)),
+        };
+    }
+    if entry_path.is_absolute() {
+        return Err(anyhow::anyhow!("Refusing to restore absolute tar entry '{}'", entry_name));
+    }
+    match &options.target_dir {
+        Some(target_dir) => Ok(target_dir.join(entry_path)),
+        None => Ok(archive_directory(archive_path).join(entry_path)),
+    }
+}
+
+fn archive_directory(archive_path: &Path) -> &Path {
+    archive_path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Modification time of a zip entry - zip keeps local time at two second resolution
+fn zip_mtime(datetime: zip::DateTime) -> Option<SystemTime> {
+    let local = NaiveDate::from_ymd_opt(datetime.year() as i32, datetime.month() as u32, datetime.day() as u32)?
+        .and_hms_opt(datetime.hour() as u32, datetime.minute() as u32, datetime.second() as u32)?;
+    Local.from_local_datetime(&local).earliest().map(SystemTime::from)
+}
+
+/// Write one entry to its destination with its mode and mtime - an existing file is only replaced with `force`
+fn restore_entry<R: io::Read>(mut reader: R, destination: &Path, mode: Option<u32>, mtime: Option<SystemTime>, options: &RestoreOptions) -> anyhow::Result<()> {
+    if destination.is_dir() {
+        return Err(anyhow::anyhow!("'{}' is a directory", destination.display()));
+    }
+    if destination.exists() && !options.force {
+        return Err(anyhow::anyhow!("'{}' already exists - use --force to replace it", destination.display()));
+    }
+    if let Some(parent) = destination.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomically(destination, |mut file| {
+        io::copy(&mut reader, &mut file)?;
+        if let Some(mode) = mode {
+            file.set_permissions(fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+        if let Some(mtime) = mtime {
+            file.set_modified(mtime)?;
+        }
+        Ok(file)
+    })
+}
+
+/// Extract every entry of a tar, tar.gz or zip archive back to where it was archived from, or into `target_dir`
+/// Returns the restored paths
+pub fn restore_archive(archive_path: &Path, options: &RestoreOptions) -> anyhow::Result<Vec<PathBuf>> {
+    let archive_type = ArchiveType::from_archive_path(&archive_path.to_string_lossy())
+        .filter(|archive_type| !archive_type.is_single_file())
+        .ok_or_else(|| anyhow::anyhow!("'{}' is not a tar, tar.gz or zip archive", archive_path.display()))?;
+    let archive_file = fs::File::open(archive_path)?;
+    let mut restored = Vec::new();
+
+    match archive_type {
+        ArchiveType::Zip => {
+            let mut zip_archive = zip::ZipArchive::new(archive_file)?;
+            for index in 0..zip_archive.len() {
+                let entry = zip_archive.by_index(index)?;
+                if entry.is_dir() {
+                    continue;
+                }
+                if entry.is_symlink() {
+                    return Err(anyhow::anyhow!("Refusing to restore entry '{}' - only regular files are restored", entry.name()));
+                }
+                let destination = restore_destination(archive_path, entry.name(), &archive_type, options)?;
+                let mode = entry.unix_mode();
+                let mtime = entry.last_modified().and_then(zip_mtime);
+                restore_entry(entry, &destination, mode, mtime, options)?;
+                restored.push(destination);
+            }
+        }
+        _ => {
+            let reader: Box<dyn io::Read> = match archive_type {
+                ArchiveType::TarGunzip => Box::new(flate2::read::MultiGzDecoder::new(archive_file)),
+                _ => Box::new(archive_file),
+            };
+            let mut tar_archive = tar::Archive::new(reader);
+            for entry in tar_archive.entries()? {
+                let entry = entry?;
+                let name = entry.path()?.to_string_lossy().to_string();
+                match entry.header().entry_type() {
+                    entry_type if entry_type.is_dir() => continue,
+                    entry_type if entry_type.is_file() => {}
+                    // Links, devices and fifos are never written - as plain files they would come back empty
+                    _ => return Err(anyhow::anyhow!("Refusing to restore entry '{}' - only regular files are restored", name)),
+                }
+                let destination = restore_destination(archive_path, &name, &archive_type, options)?;
+                let mode = entry.header().mode().ok();
+                let mtime = entry.header().mtime().ok().map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds));
+                restore_entry(entry, &destination, mode, mtime, options)?;
+                restored.push(destination);
+            }
+        }
+    }
+    Ok(restored)
+}
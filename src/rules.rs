@@ -0,0 +1,505 @@
+//! Ordered decision rules
+//!
+//! A rule set is an ordered list of rules, each a set of conditions on a file mapped to an action. The first rule
+//! whose conditions all pass decides, and files no rule matches are left unchanged. Rules are written in TOML:
+//!
+//! ```toml
+//! [[rule]]
+//! name = "expire-archives"
+//! action = "remove"
+//! age = "> keep-days"
+//! file_type = ["archive"]
+//!
+//! [[rule]]
+//! name = "rotate-app-logs"
+//! action = "archive"
+//! age = ">= 1"
+//! glob = "app-*.log"
+//! owner = "www-data"
+//! ```
+//!
+//! Conditions: `age` in days (against a number or `keep-days`), `size` (e.g. `"> 100M"`), `file_type` (extensions,
+//! plus `archive` for every archive extension), `glob` on the file name (or the path when the pattern has a `/`),
+//! `regex` on the path and `owner` (user name or uid). Every condition but `age` and `size` has a `not_` form.
+//! The behaviour of earlier releases ships as the `classic` preset, the default
+
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Local, Utc};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::explain::{ConditionCheck, Explanation, RuleCheck};
+use crate::{ARCHIVE_EXTENSIONS, Action, get_file_extension, parse_size};
+
+/// Name of the preset used when no rules are given
+pub const DEFAULT_PRESET: &str = "classic";
+
+/// Archive type files past keep-days are removed, other known files up to a day old are archived and known files
+/// between two days old and keep-days are truncated
+const CLASSIC_PRESET: &str = r#"
+[[rule]]
+name = "remove-old-archives"
+action = "remove"
+age = "> keep-days"
+file_type = ["archive"]
+
+[[rule]]
+name = "archive-recent-logs"
+action = "archive"
+age = ["< keep-days", "<= 1"]
+not_file_type = ["archive", "unknown"]
+
+[[rule]]
+name = "truncate-older-logs"
+action = "truncate"
+age = ["<= keep-days", "> 1"]
+not_file_type = ["archive", "unknown"]
+"#;
+
+/// Built-in presets by name
+pub const PRESETS: [(&str, &str); 1] = [("classic", CLASSIC_PRESET)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    pub fn compare<T: PartialOrd>(&self, left: T, right: T) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterEqual => left >= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+        }
+    }
+
+    /// Split `"<= 7"` into the comparison and the rest
+    fn split(condition: &str) -> anyhow::Result<(Comparison, &str)> {
+        let condition = condition.trim();
+        let operators = [
+            ("<=", Comparison::LessEqual),
+            (">=", Comparison::GreaterEqual),
+            ("==", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+        ];
+        operators
+            .iter()
+            .find_map(|(operator, comparison)| condition.strip_prefix(operator).map(|rest| (*comparison, rest.trim())))
+            .ok_or_else(|| anyhow::anyhow!("Invalid condition '{}' - expected one of <, <=, >, >=, ==, != and a value", condition))
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// What a file's age is compared with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgeLimit {
+    Days(i64),
+    /// The run's `--keep-days`
+    KeepDays,
+}
+
+impl fmt::Display for AgeLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgeLimit::Days(days) => write!(f, "{}", days),
+            AgeLimit::KeepDays => write!(f, "keep-days"),
+        }
+    }
+}
+
+/// One condition of a rule
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    /// Whole days since the last modification
+    Age(Comparison, AgeLimit),
+    /// Size in bytes - the size as written is kept for explanations
+    Size(Comparison, u64, String),
+    FileType { types: Vec<String>, negate: bool },
+    Glob { pattern: String, regex: Regex, negate: bool },
+    Regex { regex: Regex, negate: bool },
+    Owner { owner: String, uid: u32, negate: bool },
+}
+
+/// What the conditions look at
+#[derive(Debug, Clone)]
+pub struct FileFacts {
+    pub path: String,
+    pub file_name: String,
+    pub extension: String,
+    pub modified: DateTime<Local>,
+    pub age_days: i64,
+    pub age_hours: i64,
+    pub size: u64,
+    pub uid: u32,
+}
+
+impl FileFacts {
+    pub fn read(file: &Path) -> anyhow::Result<FileFacts> {
+        let metadata = fs::metadata(file)?;
+        let modified: DateTime<Utc> = metadata.modified()?.into();
+        let age = Utc::now().signed_duration_since(modified);
+        let path = file.to_string_lossy().to_string();
+        Ok(FileFacts {
+            file_name: file.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| path.clone()),
+            extension: get_file_extension(&path),
+            path,
+            modified: modified.into(),
+            age_days: age.num_days(),
+            age_hours: age.num_hours(),
+            size: metadata.len(),
+            uid: metadata.uid(),
+        })
+    }
+}
+
+/// Wording of a condition depending on whether it is negated
+fn negated(negate: bool, positive: &str, negative: &str) -> String {
+    if negate { negative.to_string() } else { positive.to_string() }
+}
+
+impl Predicate {
+    pub fn check(&self, facts: &FileFacts, threshold_days: i64) -> ConditionCheck {
+        let (condition, actual, passed) = match self {
+            Predicate::Age(comparison, limit) => {
+                let limit_days = match limit {
+                    AgeLimit::Days(days) => *days,
+                    AgeLimit::KeepDays => threshold_days,
+                };
+                (
+                    format!("age {} {}", comparison, limit),
+                    format!("{} {} {}", facts.age_days, comparison, limit_days),
+                    comparison.compare(facts.age_days, limit_days),
+                )
+            }
+            Predicate::Size(comparison, bytes, written) => (
+                format!("size {} {}", comparison, written),
+                format!("{} {} {}", facts.size, comparison, bytes),
+                comparison.compare(facts.size, *bytes),
+            ),
+            Predicate::FileType { types, negate } => {
+                let found = types.iter().any(|file_type| match file_type.as_str() {
+                    "archive" => ARCHIVE_EXTENSIONS.contains(&facts.extension.as_str()),
+                    file_type => file_type == facts.extension,
+                });
+                (
+                    format!("file type {} [{}]", negated(*negate, "in", "not in"), types.join(", ")),
+                    facts.extension.clone(),
+                    found != *negate,
+                )
+            }
+            Predicate::Glob { pattern, regex, negate } => {
+                let subject = if pattern.contains('/') { &facts.path } else { &facts.file_name };
+                (
+                    format!("{} {} {}", if pattern.contains('/') { "path" } else { "name" }, negated(*negate, "matches", "does not match"), pattern),
+                    subject.clone(),
+                    regex.is_match(subject) != *negate,
+                )
+            }
+            Predicate::Regex { regex, negate } => (
+                format!("path {} /{}/", negated(*negate, "matches", "does not match"), regex.as_str()),
+                facts.path.clone(),
+                regex.is_match(&facts.path) != *negate,
+            ),
+            Predicate::Owner { owner, uid, negate } => (
+                format!("owner {} {} (uid {})", negated(*negate, "is", "is not"), owner, uid),
+                format!("uid {}", facts.uid),
+                (facts.uid == *uid) != *negate,
+            ),
+        };
+        ConditionCheck { condition, actual, passed }
+    }
+}
+
+/// Conditions mapped to an action
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub action: Action,
+    pub conditions: Vec<Predicate>,
+}
+
+impl Rule {
+    pub fn check(&self, facts: &FileFacts, threshold_days: i64) -> RuleCheck {
+        RuleCheck {
+            name: self.name.clone(),
+            action: self.action,
+            conditions: self.conditions.iter().map(|predicate| predicate.check(facts, threshold_days)).collect(),
+        }
+    }
+}
+
+/// Ordered rules - the first matching rule decides
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    /// Preset name or rules file the set was loaded from
+    pub name: String,
+    pub rules: Vec<Rule>,
+}
+
+/// The default preset, parsed once
+pub fn default_rules() -> &'static RuleSet {
+    static RULES: OnceLock<RuleSet> = OnceLock::new();
+    RULES.get_or_init(|| RuleSet::preset(DEFAULT_PRESET).expect("built-in presets are valid"))
+}
+
+impl RuleSet {
+    /// Built-in preset by name
+    pub fn preset(name: &str) -> anyhow::Result<RuleSet> {
+        let (_, rules) = PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown rules preset '{}'", name))?;
+        RuleSet::parse(name, rules)
+    }
+
+    /// Parse a rules file's contents
+    pub fn parse(name: &str, rules: &str) -> anyhow::Result<RuleSet> {
+        let rules_file: RulesFile = toml::from_str(rules)?;
+        if rules_file.rules.is_empty() {
+            return Err(anyhow::anyhow!("Rules have no [[rule]] tables"));
+        }
+        let rules = rules_file
+            .rules
+            .iter()
+            .map(|rule| rule.resolve().map_err(|error| error.context(format!("Rule '{}'", rule.name))))
+            .collect::<anyhow::Result<Vec<Rule>>>()?;
+        Ok(RuleSet { name: name.to_string(), rules })
+    }
+
+    /// A preset name, or else the path of a rules file
+    pub fn load(preset_or_path: &str) -> anyhow::Result<RuleSet> {
+        if PRESETS.iter().any(|(preset, _)| *preset == preset_or_path) {
+            return RuleSet::preset(preset_or_path);
+        }
+        let rules = fs::read_to_string(preset_or_path)
+            .map_err(|error| anyhow::anyhow!("Unable to read rules '{}': {}", preset_or_path, error))?;
+        RuleSet::parse(preset_or_path, &rules).map_err(|error| error.context(format!("Invalid rules '{}'", preset_or_path)))
+    }
+
+    /// Action for a file - unchanged when no rule matches
+    /// Goes through `explain`, so `logrotate explain` shows exactly what decided here
+    pub fn decide(&self, file: &Path, threshold_days: i64) -> anyhow::Result<Action> {
+        Ok(self.explain(file, threshold_days)?.action)
+    }
+
+    /// Check every rule against a file, keeping each condition's values
+    pub fn explain(&self, file: &Path, threshold_days: i64) -> anyhow::Result<Explanation> {
+        let facts = FileFacts::read(file)?;
+        let rules: Vec<RuleCheck> = self.rules.iter().map(|rule| rule.check(&facts, threshold_days)).collect();
+        let decided = rules.iter().find(|rule| rule.matched());
+        Ok(Explanation {
+            file: file.to_path_buf(),
+            rule_set: self.name.clone(),
+            extension: facts.extension.clone(),
+            modified: facts.modified,
+            age_days: facts.age_days,
+            age_hours: facts.age_hours,
+            threshold_days,
+            action: decided.map(|rule| rule.action).unwrap_or(Action::Unchanged),
+            decided_by: decided.map(|rule| rule.name.clone()),
+            rules,
+        })
+    }
+}
+
+/// A rules file as written
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RulesFile {
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<RuleConfig>,
+}
+
+/// One or several values of a condition
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn values(&self) -> Vec<String> {
+        match self {
+            OneOrMany::One(value) => vec![value.clone()],
+            OneOrMany::Many(values) => values.clone(),
+        }
+    }
+}
+
+/// One `[[rule]]` table as written - every condition given has to pass
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    pub name: String,
+    pub action: String,
+    pub age: Option<OneOrMany>,
+    pub size: Option<OneOrMany>,
+    pub file_type: Option<OneOrMany>,
+    pub not_file_type: Option<OneOrMany>,
+    pub glob: Option<String>,
+    pub not_glob: Option<String>,
+    pub regex: Option<String>,
+    pub not_regex: Option<String>,
+    pub owner: Option<String>,
+    pub not_owner: Option<String>,
+}
+
+impl RuleConfig {
+    pub fn resolve(&self) -> anyhow::Result<Rule> {
+        let action = match self.action.as_str() {
+            "archive" => Action::Archive,
+            "remove" => Action::Remove,
+            "truncate" => Action::Truncate,
+            "unchanged" | "keep" => Action::Unchanged,
+            action => return Err(anyhow::anyhow!("Invalid action '{}' - possible values: archive, remove, truncate, unchanged", action)),
+        };
+
+        let mut conditions = Vec::new();
+        for age in self.age.iter().flat_map(OneOrMany::values) {
+            let (comparison, limit) = Comparison::split(&age)?;
+            let limit = match limit {
+                "keep-days" => AgeLimit::KeepDays,
+                days => AgeLimit::Days(
+                    days.trim_end_matches('d')
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid age '{}' - expected a number of days or keep-days", age))?,
+                ),
+            };
+            conditions.push(Predicate::Age(comparison, limit));
+        }
+        for size in self.size.iter().flat_map(OneOrMany::values) {
+            let (comparison, bytes) = Comparison::split(&size)?;
+            conditions.push(Predicate::Size(comparison, parse_size(bytes)?, bytes.to_string()));
+        }
+        for (types, negate) in [(&self.file_type, false), (&self.not_file_type, true)] {
+            if let Some(types) = types {
+                let types = types.values().iter().map(|file_type| file_type.to_lowercase()).collect();
+                conditions.push(Predicate::FileType { types, negate });
+            }
+        }
+        for (pattern, negate) in [(&self.glob, false), (&self.not_glob, true)] {
+            if let Some(pattern) = pattern {
+                conditions.push(Predicate::Glob { pattern: pattern.clone(), regex: glob_regex(pattern)?, negate });
+            }
+        }
+        for (regex, negate) in [(&self.regex, false), (&self.not_regex, true)] {
+            if let Some(regex) = regex {
+                let regex = Regex::new(regex).map_err(|error| anyhow::anyhow!("Invalid regex '{}': {}", regex, error))?;
+                conditions.push(Predicate::Regex { regex, negate });
+            }
+        }
+        for (owner, negate) in [(&self.owner, false), (&self.not_owner, true)] {
+            if let Some(owner) = owner {
+                conditions.push(Predicate::Owner { owner: owner.clone(), uid: resolve_uid(owner)?, negate });
+            }
+        }
+
+        Ok(Rule { name: self.name.clone(), action, conditions })
+    }
+}
+
+/// Anchored regex for a glob - `*` and `?` stay within a path component, `**` crosses them, `[...]` is a class
+pub fn glob_regex(pattern: &str) -> anyhow::Result<Regex> {
+    let mut regex = String::from("^");
+    let mut characters = pattern.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            '*' if characters.peek() == Some(&'*') => {
+                characters.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                regex.push('[');
+                if characters.peek() == Some(&'!') {
+                    characters.next();
+                    regex.push('^');
+                }
+                // A `]` right after the opening (or the `!`) is part of the class, as in POSIX globs
+                let mut class = Vec::new();
+                if let Some(bracket) = characters.next_if_eq(&']') {
+                    class.push(bracket);
+                }
+                let mut closed = false;
+                for class_character in characters.by_ref() {
+                    if class_character == ']' {
+                        closed = true;
+                        break;
+                    }
+                    class.push(class_character);
+                }
+                if !closed {
+                    return Err(anyhow::anyhow!("Invalid glob '{}': '[' is never closed", pattern));
+                }
+                // Everything is literal but a `-` between two characters - escaping keeps `[`, `&&`, `--` and `~~`
+                // from turning into nested classes and set operations of the regex syntax
+                let (mut after_range, mut in_range) = (false, false);
+                for (index, class_character) in class.iter().enumerate() {
+                    let range = *class_character == '-' && index > 0 && index + 1 < class.len() && !after_range && !in_range;
+                    (after_range, in_range) = (in_range, range);
+                    if range {
+                        regex.push('-');
+                    } else if "\\[]&~-^".contains(*class_character) {
+                        regex.push('\\');
+                        regex.push(*class_character);
+                    } else {
+                        regex.push(*class_character);
+                    }
+                }
+                regex.push(']');
+            }
+            character => regex.push_str(&regex::escape(&character.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(|error| anyhow::anyhow!("Invalid glob '{}': {}", pattern, error))
+}
+
+/// uid of a user name, or a numeric uid as is
+pub fn resolve_uid(owner: &str) -> anyhow::Result<u32> {
+    if let Ok(uid) = u32::from_str(owner) {
+        return Ok(uid);
+    }
+    let name = CString::new(owner)?;
+    let mut password: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the duration of the call and the buffer length is its actual length
+    let status = unsafe { libc::getpwnam_r(name.as_ptr(), &mut password, buffer.as_mut_ptr(), buffer.len(), &mut result) };
+    if status != 0 || result.is_null() {
+        return Err(anyhow::anyhow!("Unknown owner '{}'", owner));
+    }
+    Ok(password.pw_uid)
+}
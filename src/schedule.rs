@@ -0,0 +1,233 @@
+//! Run schedules for daemon targets
+//!
+//! A schedule is either a fixed interval (`every 15m`), a shorthand (`hourly`, `daily`, `daily at 03:00`, `weekly`,
+//! `monthly`) or a standard five field cron expression (`minute hour day-of-month month day-of-week`) in local time
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveDateTime, NaiveTime, TimeZone, Timelike};
+
+/// When a target runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// Fixed delay between the end of one run and the start of the next
+    Interval(Duration),
+    Cron(CronExpression),
+}
+
+impl Schedule {
+    /// Next time the schedule fires strictly after `after`
+    pub fn next_after(&self, after: DateTime<Local>) -> DateTime<Local> {
+        match self {
+            Schedule::Interval(interval) => ChronoDuration::from_std(*interval)
+                .ok()
+                .and_then(|interval| after.checked_add_signed(interval))
+                .unwrap_or_else(|| never_after(after)),
+            Schedule::Cron(expression) => expression.next_after(after),
+        }
+    }
+
+    /// Whether the schedule can fire more than once on the same day
+    pub fn fires_more_than_daily(&self) -> bool {
+        match self {
+            Schedule::Interval(interval) => interval.as_secs() < 86400,
+            Schedule::Cron(expression) => expression.minutes.count_ones() > 1 || expression.hours.count_ones() > 1,
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Interval(interval) => write!(f, "every {}s", interval.as_secs()),
+            Schedule::Cron(expression) => write!(f, "{}", expression),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let schedule = s.trim().to_lowercase();
+        let cron = match schedule.as_str() {
+            "hourly" => "0 * * * *".to_string(),
+            "daily" => "0 0 * * *".to_string(),
+            "weekly" => "0 0 * * 0".to_string(),
+            "monthly" => "0 0 1 * *".to_string(),
+            _ if schedule.starts_with("daily at ") => {
+                let time = NaiveTime::parse_from_str(schedule.trim_start_matches("daily at ").trim(), "%H:%M")
+                    .map_err(|_| anyhow::anyhow!("Invalid time in schedule '{}' - expected HH:MM", s))?;
+                format!("{} {} * * *", time.minute(), time.hour())
+            }
+            _ if schedule.starts_with("every ") => {
+                return Ok(Schedule::Interval(parse_interval(schedule.trim_start_matches("every ").trim())?));
+            }
+            _ => schedule.clone(),
+        };
+        Ok(Schedule::Cron(cron.parse()?))
+    }
+}
+
+/// Parse an interval such as `30s`, `15m`, `2h` or `1d`
+pub fn parse_interval(interval: &str) -> anyhow::Result<Duration> {
+    let split_at = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(interval.len());
+    let (value, unit) = interval.split_at(split_at);
+
+    let value: u64 = value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid interval '{}' - expected e.g. 30s, 15m, 2h or 1d", interval))?;
+    let multiplier = match unit.trim() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(anyhow::anyhow!("Unknown unit in interval '{}' - use s, m, h or d", interval)),
+    };
+    let seconds = value
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow::anyhow!("Interval '{}' is too long", interval))?;
+    if seconds == 0 {
+        return Err(anyhow::anyhow!("Interval '{}' must be longer than zero", interval));
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Five field cron expression, each field kept as a bitmask of the values it matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpression {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    /// Sunday is 0 (7 is accepted as well)
+    days_of_week: u64,
+    /// With both day fields restricted a day matches when either does, as in cron
+    days_of_month_restricted: bool,
+    days_of_week_restricted: bool,
+}
+
+impl FromStr for CronExpression {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields.as_slice() else {
+            return Err(anyhow::anyhow!(
+                "Invalid schedule '{}' - expected hourly, daily, daily at HH:MM, weekly, monthly, every <interval> or a five field cron expression",
+                s,
+            ));
+        };
+
+        let mut days_of_week_mask = parse_cron_field(days_of_week, 0, 7)?;
+        if days_of_week_mask & (1 << 7) != 0 {
+            days_of_week_mask = (days_of_week_mask | 1) & !(1 << 7);
+        }
+
+        Ok(CronExpression {
+            source: s.to_string(),
+            minutes: parse_cron_field(minutes, 0, 59)?,
+            hours: parse_cron_field(hours, 0, 23)?,
+            days_of_month: parse_cron_field(days_of_month, 1, 31)?,
+            months: parse_cron_field(months, 1, 12)?,
+            days_of_week: days_of_week_mask,
+            // Like Vixie cron, a field starting with `*` (e.g. `*/2`) does not count as restricted
+            days_of_month_restricted: !days_of_month.starts_with('*'),
+            days_of_week_restricted: !days_of_week.starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for CronExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Parse one cron field - `*`, `5`, `1-5`, `*/15`, `0-30/10` and comma separated lists of those
+fn parse_cron_field(field: &str, min: u32, max: u32) -> anyhow::Result<u64> {
+    let invalid = || anyhow::anyhow!("Invalid cron field '{}' - values must be within {}-{}", field, min, max);
+    let mut mask = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (start.parse().map_err(|_| invalid())?, end.parse().map_err(|_| invalid())?),
+                None => {
+                    let value = range.parse().map_err(|_| invalid())?;
+                    // "5/10" runs from 5 to the end of the range
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+impl CronExpression {
+    fn matches_day(&self, time: &NaiveDateTime) -> bool {
+        let day_of_month = self.days_of_month & (1 << time.day()) != 0;
+        let day_of_week = self.days_of_week & (1 << time.weekday().num_days_from_sunday()) != 0;
+        match (self.days_of_month_restricted, self.days_of_week_restricted) {
+            (true, true) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+
+    /// Next matching minute strictly after `after`, skipping local times that do not exist (DST gaps)
+    pub fn next_after(&self, after: DateTime<Local>) -> DateTime<Local> {
+        let mut time = after
+            .naive_local()
+            .with_second(0)
+            .and_then(|time| time.with_nanosecond(0))
+            .unwrap_or(after.naive_local())
+            + ChronoDuration::minutes(1);
+
+        // Five years of skipping covers every satisfiable expression, e.g. 29 February on a Monday
+        let limit = time + ChronoDuration::days(5 * 366);
+        while time < limit {
+            if self.months & (1 << time.month()) == 0 {
+                time = (time.date().with_day(1).unwrap() + chrono::Months::new(1)).and_hms_opt(0, 0, 0).unwrap();
+                continue;
+            }
+            if !self.matches_day(&time) {
+                time = (time.date() + ChronoDuration::days(1)).and_hms_opt(0, 0, 0).unwrap();
+                continue;
+            }
+            if self.hours & (1 << time.hour()) == 0 {
+                time = time.with_minute(0).unwrap() + ChronoDuration::hours(1);
+                continue;
+            }
+            if self.minutes & (1 << time.minute()) == 0 {
+                time += ChronoDuration::minutes(1);
+                continue;
+            }
+
+            if let Some(local) = Local.from_local_datetime(&time).earliest() {
+                return local;
+            }
+            time += ChronoDuration::minutes(1);
+        }
+        // Never matches (e.g. 31 February)
+        never_after(after)
+    }
+}
+
+/// Stand-in for "never" - far enough out that the target does not run again
+fn never_after(after: DateTime<Local>) -> DateTime<Local> {
+    after + ChronoDuration::days(100 * 366)
+}
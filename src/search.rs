@@ -0,0 +1,179 @@
+//! Search inside archives without extracting them
+//!
+//! Every archive the tool writes is streamed entry by entry through its decoder and matched line by line, several
+//! archives at a time, and matches are handed on as they are found rather than collected. Archives whose lines lie outside the `--since` / `--until` window are not opened at all - the
+//! catalog records the time range of each archive, and a dated archive outside of it holds the lines written between
+//! the previous rotation of its family and its own date
+
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDate};
+use regex::bytes::Regex;
+
+use crate::{ArchiveType, archive_families, single_file_entry_name};
+
+/// One matching line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub archive: PathBuf,
+    pub entry: String,
+    pub line: String,
+}
+
+impl fmt::Display for SearchMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.archive.display(), self.entry, self.line)
+    }
+}
+
+/// Archives of a file list that may hold lines from the `since` - `until` window (both inclusive), family by family and oldest first
+/// The time range recorded in the catalog decides when there is one, otherwise the dates in the archive names.
+/// Undated (numbered) archives outside the catalog cannot be placed and are always searched
+pub fn archives_in_window(file_list: &[PathBuf], since: Option<NaiveDate>, until: Option<NaiveDate>) -> Vec<PathBuf> {
+    let mut selected = Vec::new();
+    for archives in archive_families(file_list).into_values() {
+        for (index, listing) in archives.iter().enumerate().rev() {
+            let (first_day, last_day) = match listing.catalog.as_ref().map(|entry| (entry.first_time(), entry.last_time())) {
+                Some((Some(first), Some(last))) => (Some(local_day(first)), Some(local_day(last))),
+                // Lines up to the date of the archive, written after the previous rotation of the family
+                _ => (archives.get(index + 1).and_then(|older| older.date), listing.date),
+            };
+            let ends_before = matches!((last_day, since), (Some(last_day), Some(since)) if last_day < since);
+            let starts_after = matches!((first_day, until), (Some(first_day), Some(until)) if first_day > until);
+            if !ends_before && !starts_after {
+                selected.push(listing.archive.clone());
+            }
+        }
+    }
+    selected
+}
+
+fn local_day(time: DateTime<FixedOffset>) -> NaiveDate {
+    time.with_timezone(&Local).date_naive()
+}
+
+/// Matches an archive can get ahead of the one being printed - a worker waits once its archive has this many queued
+const MATCH_BUFFER: usize = 1024;
+
+/// What a search turns up, in archive order
+#[derive(Debug)]
+pub enum SearchEvent {
+    Match(SearchMatch),
+    /// The archive could not be read (further) - matches found before the error have already been reported
+    Failed(PathBuf, anyhow::Error),
+}
+
+/// Match every line of one entry
+fn search_reader<R: io::Read>(reader: R, archive_path: &Path, entry: &str, pattern: &Regex, found: &mut dyn FnMut(SearchMatch)) -> io::Result<()> {
+    let mut reader = io::BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        while matches!(line.last(), Some(b'\n' | b'\r')) {
+            line.pop();
+        }
+        if pattern.is_match(&line) {
+            found(SearchMatch {
+                archive: archive_path.to_path_buf(),
+                entry: entry.to_string(),
+                line: String::from_utf8_lossy(&line).to_string(),
+            });
+        }
+    }
+}
+
+fn search_tar<R: io::Read>(mut tar_archive: tar::Archive<R>, archive_path: &Path, pattern: &Regex, found: &mut dyn FnMut(SearchMatch)) -> anyhow::Result<()> {
+    for entry in tar_archive.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        search_reader(entry, archive_path, &name, pattern, found)?;
+    }
+    Ok(())
+}
+
+/// Hand every matching line of an archive to `found` as it is read, decompressed on the fly
+pub fn scan_archive(archive_path: &Path, pattern: &Regex, found: &mut dyn FnMut(SearchMatch)) -> anyhow::Result<()> {
+    let archive_str = archive_path.to_string_lossy();
+    if archive_str.ends_with(".age") {
+        return Err(anyhow::anyhow!("'{}' is encrypted", archive_str));
+    }
+    let archive_type = ArchiveType::from_archive_path(&archive_str)
+        .ok_or_else(|| anyhow::anyhow!("'{}' is not a tar, tar.gz, zip, gz, zst or xz archive", archive_str))?;
+    let archive_file = fs::File::open(archive_path)?;
+
+    match archive_type {
+        ArchiveType::Tar => search_tar(tar::Archive::new(archive_file), archive_path, pattern, found)?,
+        ArchiveType::TarGunzip => search_tar(tar::Archive::new(flate2::read::MultiGzDecoder::new(archive_file)), archive_path, pattern, found)?,
+        ArchiveType::Zip => {
+            let mut zip_archive = zip::ZipArchive::new(archive_file)?;
+            for index in 0..zip_archive.len() {
+                let entry = zip_archive.by_index(index)?;
+                if entry.is_dir() {
+                    continue;
+                }
+                let name = entry.name().to_string();
+                search_reader(entry, archive_path, &name, pattern, found)?;
+            }
+        }
+        ArchiveType::Gzip => search_reader(flate2::read::MultiGzDecoder::new(archive_file), archive_path, &single_file_entry_name(&archive_str), pattern, found)?,
+        ArchiveType::Zstd => search_reader(zstd::stream::read::Decoder::new(archive_file)?, archive_path, &single_file_entry_name(&archive_str), pattern, found)?,
+        ArchiveType::Xz => search_reader(xz2::read::XzDecoder::new(archive_file), archive_path, &single_file_entry_name(&archive_str), pattern, found)?,
+    }
+    Ok(())
+}
+
+/// Every matching line of an archive
+pub fn search_archive(archive_path: &Path, pattern: &Regex) -> anyhow::Result<Vec<SearchMatch>> {
+    let mut matches = Vec::new();
+    scan_archive(archive_path, pattern, &mut |found| matches.push(found))?;
+    Ok(matches)
+}
+
+/// Search the archives of a file list within the window, `jobs` archives at a time, handing what turns up to
+/// `on_event` on the calling thread as soon as it is found - in archive order
+/// Each archive streams into a bounded channel of its own and the channels are drained one after the other. Workers
+/// take archives in order, so the archive being drained always has a worker and the ones behind it only ever hold
+/// `MATCH_BUFFER` matches each
+pub fn search_archives<F: FnMut(SearchEvent)>(file_list: &[PathBuf], pattern: &Regex, since: Option<NaiveDate>, until: Option<NaiveDate>, jobs: usize, mut on_event: F) {
+    let archives = archives_in_window(file_list, since, until);
+    let (senders, receivers): (Vec<SyncSender<SearchEvent>>, Vec<Receiver<SearchEvent>>) =
+        archives.iter().map(|_| mpsc::sync_channel(MATCH_BUFFER)).unzip();
+    let senders: Mutex<Vec<Option<SyncSender<SearchEvent>>>> = Mutex::new(senders.into_iter().map(Some).collect());
+    let next_archive = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, archives.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let index = next_archive.fetch_add(1, Ordering::Relaxed);
+                    let Some(archive) = archives.get(index) else { break };
+                    let sender = senders.lock().unwrap()[index].take().expect("every archive is searched once");
+                    // Sending only fails once the receiving side is gone, and then nobody is waiting for the results
+                    let result = scan_archive(archive, pattern, &mut |found| {
+                        sender.send(SearchEvent::Match(found)).ok();
+                    });
+                    if let Err(error) = result {
+                        sender.send(SearchEvent::Failed(archive.clone(), error)).ok();
+                    }
+                }
+            });
+        }
+        // A channel ends when its worker drops the sender after the archive
+        for receiver in receivers {
+            receiver.into_iter().for_each(&mut on_event);
+        }
+    });
+}
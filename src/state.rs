@@ -0,0 +1,108 @@
+//! Per-directory run state
+//!
+//! Every rotation of a directory (from the command line or the daemon) adds a summary to `.logrotate-state` in that
+//! directory, so `status` can show when it last ran and what it did. Only the most recent runs are kept
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::{Action, ActionRecord, write_atomically};
+
+/// Name of the state file kept in each rotated directory
+pub const STATE_FILE_NAME: &str = ".logrotate-state";
+
+/// Runs kept in the state file, newest last
+pub const KEPT_RUNS: usize = 20;
+
+/// Held while the state file is read, updated and written back
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+/// What one run did
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunSummary {
+    /// RFC 3339, local time
+    pub started: String,
+    pub finished: String,
+    pub archived: usize,
+    pub removed: usize,
+    pub truncated: usize,
+    pub skipped: usize,
+    pub unchanged: usize,
+    /// Size of the files archived, before compression
+    pub bytes_archived: u64,
+    pub bytes_removed: u64,
+    /// `file: reason` for every skipped file
+    pub errors: Vec<String>,
+}
+
+impl RunSummary {
+    pub fn from_report(started: DateTime<Local>, finished: DateTime<Local>, report: &[ActionRecord]) -> RunSummary {
+        let mut summary = RunSummary {
+            started: started.to_rfc3339_opts(SecondsFormat::Secs, false),
+            finished: finished.to_rfc3339_opts(SecondsFormat::Secs, false),
+            ..Default::default()
+        };
+        for record in report {
+            match record.action {
+                Action::Archive => {
+                    summary.archived += 1;
+                    summary.bytes_archived += record.bytes;
+                }
+                Action::Remove => {
+                    summary.removed += 1;
+                    summary.bytes_removed += record.bytes;
+                }
+                Action::Truncate => summary.truncated += 1,
+                Action::Unchanged => summary.unchanged += 1,
+                Action::Skipped => {
+                    summary.skipped += 1;
+                    summary.errors.push(format!("{}: {}", record.file.display(), record.reason.as_deref().unwrap_or("unknown")));
+                }
+            }
+        }
+        summary
+    }
+}
+
+/// Contents of a state file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunState {
+    pub runs: Vec<RunSummary>,
+}
+
+/// State file of a provided directory
+pub fn state_path(dir_path: &Path) -> PathBuf {
+    dir_path.join(STATE_FILE_NAME)
+}
+
+/// Read a directory's state - empty when it was never rotated
+pub fn read_state(dir_path: &Path) -> anyhow::Result<RunState> {
+    let path = state_path(dir_path);
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|error| anyhow::anyhow!("Malformed state file '{}': {}", path.display(), error)),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(RunState::default()),
+        Err(error) => Err(anyhow::anyhow!("Unable to read state file '{}': {}", path.display(), error)),
+    }
+}
+
+/// Add a run to a directory's state, dropping the oldest runs beyond `KEPT_RUNS`
+pub fn record_run(dir_path: &Path, summary: RunSummary) -> anyhow::Result<()> {
+    let _guard = STATE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut state = read_state(dir_path)?;
+    state.runs.push(summary);
+    let excess = state.runs.len().saturating_sub(KEPT_RUNS);
+    state.runs.drain(..excess);
+
+    let contents = serde_json::to_string_pretty(&state)?;
+    write_atomically(&state_path(dir_path), |mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.write_all(b"\n")?;
+        Ok(file)
+    })
+}
@@ -0,0 +1,216 @@
+//! RFC 5424 syslog forwarding of archive, remove and truncate actions
+//!
+//! One datagram per action, to `/dev/log` or another Unix or UDP endpoint. The action details travel as structured
+//! data under `logrotate@32473` (the documentation enterprise number of RFC 5612), e.g.
+//!
+//! ```text
+//! <29>1 2025-09-26T03:00:00.000000+00:00 web1 logrotate 4242 remove [logrotate@32473 file="/var/log/app.log_2025_09_18.tar.gz" action="remove" extension="gz" bytes="1024"] File: ...
+//! ```
+
+use std::fmt;
+use std::io;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::{Local, SecondsFormat};
+use clap::ValueEnum;
+
+use crate::{Action, ActionRecord, get_file_extension};
+
+/// Default local syslog socket
+pub const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Structured data ID of every message
+pub const SD_ID: &str = "logrotate@32473";
+
+/// Syslog facility of the messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Default)]
+pub enum SyslogFacility {
+    User,
+    #[default]
+    Daemon,
+    Auth,
+    Syslog,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    pub fn code(&self) -> u8 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Auth => 4,
+            SyslogFacility::Syslog => 5,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
+}
+
+/// Where messages go - a Unix datagram socket path, or `udp://host:port`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogEndpoint {
+    Unix(PathBuf),
+    Udp(String),
+}
+
+impl FromStr for SyslogEndpoint {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(address) = s.strip_prefix("udp://") {
+            if !address.contains(':') {
+                return Err(anyhow::anyhow!("Invalid syslog endpoint '{}' - expected udp://host:port", s));
+            }
+            return Ok(SyslogEndpoint::Udp(address.to_string()));
+        }
+        let path = s.strip_prefix("unix://").unwrap_or(s);
+        if !path.starts_with('/') {
+            return Err(anyhow::anyhow!("Invalid syslog endpoint '{}' - expected an absolute socket path or udp://host:port", s));
+        }
+        Ok(SyslogEndpoint::Unix(PathBuf::from(path)))
+    }
+}
+
+impl fmt::Display for SyslogEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyslogEndpoint::Unix(path) => write!(f, "{}", path.display()),
+            SyslogEndpoint::Udp(address) => write!(f, "udp://{}", address),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum SyslogSocket {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+/// Connected syslog endpoint
+#[derive(Debug)]
+pub struct SyslogSender {
+    socket: SyslogSocket,
+    facility: SyslogFacility,
+    hostname: String,
+}
+
+impl SyslogSender {
+    pub fn connect(endpoint: &SyslogEndpoint, facility: SyslogFacility) -> anyhow::Result<SyslogSender> {
+        let socket = match endpoint {
+            SyslogEndpoint::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket
+                    .connect(path)
+                    .map_err(|error| anyhow::anyhow!("Unable to connect to syslog socket '{}': {}", path.display(), error))?;
+                SyslogSocket::Unix(socket)
+            }
+            SyslogEndpoint::Udp(address) => {
+                let socket = UdpSocket::bind(if address.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" })?;
+                socket
+                    .connect(address)
+                    .map_err(|error| anyhow::anyhow!("Unable to resolve syslog endpoint '{}': {}", address, error))?;
+                SyslogSocket::Udp(socket)
+            }
+        };
+        Ok(SyslogSender { socket, facility, hostname: hostname() })
+    }
+
+    /// Send the message for an action record - unchanged and skipped files are not sent
+    pub fn send_record(&self, record: &ActionRecord) -> io::Result<()> {
+        let Some(message) = format_record(record, self.facility, &self.hostname) else {
+            return Ok(());
+        };
+        match &self.socket {
+            SyslogSocket::Unix(socket) => socket.send(message.as_bytes()),
+            SyslogSocket::Udp(socket) => socket.send(message.as_bytes()),
+        }
+        .map(|_| ())
+    }
+}
+
+/// Severity of an action - removals are notices, archiving and truncating informational
+pub fn action_severity(action: Action) -> Option<u8> {
+    match action {
+        Action::Remove => Some(5),
+        Action::Archive | Action::Truncate => Some(6),
+        Action::Unchanged | Action::Skipped => None,
+    }
+}
+
+/// Render an action record as an RFC 5424 message - None for actions that are not forwarded
+pub fn format_record(record: &ActionRecord, facility: SyslogFacility, hostname: &str) -> Option<String> {
+    let severity = action_severity(record.action)?;
+    let file = record.file.to_string_lossy();
+
+    let mut parameters = vec![
+        ("file", file.to_string()),
+        ("action", record.action.as_str().to_string()),
+        ("extension", get_file_extension(&file)),
+        ("bytes", record.bytes.to_string()),
+    ];
+    if let Some(archive) = &record.archive {
+        parameters.push(("archive", archive.to_string_lossy().to_string()));
+    }
+    if let Some(reason) = &record.reason {
+        parameters.push(("reason", reason.clone()));
+    }
+    let structured_data: String = parameters
+        .iter()
+        .map(|(name, value)| format!(" {}=\"{}\"", name, escape_param_value(value)))
+        .collect();
+
+    Some(format!(
+        "<{}>1 {} {} logrotate {} {} [{}{}] {}",
+        facility.code() as u16 * 8 + severity as u16,
+        Local::now().to_rfc3339_opts(SecondsFormat::Micros, false),
+        if hostname.is_empty() { "-" } else { hostname },
+        std::process::id(),
+        record.action.as_str(),
+        SD_ID,
+        structured_data,
+        record,
+    ))
+}
+
+/// Escape `"`, `\` and `]` in a structured data parameter value
+fn escape_param_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        if matches!(character, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+/// Host name for the HOSTNAME field - printable ASCII without spaces, as RFC 5424 requires
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: buffer is valid for writes of its full length
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return String::new();
+    }
+    buffer
+        .iter()
+        .take_while(|byte| **byte != 0)
+        .filter(|byte| byte.is_ascii_graphic())
+        .map(|byte| *byte as char)
+        .take(255)
+        .collect()
+}
@@ -0,0 +1,142 @@
+//! systemd service integration - readiness and watchdog notifications and native journal output
+//!
+//! Both talk datagrams over Unix sockets: `NOTIFY_SOCKET` for `sd_notify` states and the journal's native socket for
+//! structured entries. Without those sockets notifications are dropped and entries go to stderr
+
+use std::env;
+use std::io::{self, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::Local;
+
+use crate::{Action, ActionRecord};
+
+/// Native protocol socket of systemd-journald
+pub const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Syslog identifier of every journal entry
+pub const SYSLOG_IDENTIFIER: &str = "logrotate";
+
+/// Journal priorities (syslog severities) used by the daemon
+pub const PRIORITY_ERR: u8 = 3;
+pub const PRIORITY_WARNING: u8 = 4;
+pub const PRIORITY_INFO: u8 = 6;
+
+/// Send a state such as `READY=1` or `WATCHDOG=1` to the service manager
+/// Returns false when not started by one (no `NOTIFY_SOCKET`)
+pub fn notify(state: &str) -> io::Result<bool> {
+    match env::var("NOTIFY_SOCKET") {
+        Ok(address) if !address.is_empty() => notify_socket(&address, state).map(|_| true),
+        _ => Ok(false),
+    }
+}
+
+/// Send a state to a notification socket - a path, or an abstract socket name prefixed with `@`
+pub fn notify_socket(address: &str, state: &str) -> io::Result<()> {
+    let address = match address.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+        None => SocketAddr::from_pathname(address)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+/// How often to send `WATCHDOG=1` - half of `WatchdogSec=`, None when the watchdog is off or meant for another process
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID")
+        && pid.parse::<u32>().ok() != Some(std::process::id())
+    {
+        return None;
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Encode fields in the journal's native format - `KEY=value` lines, or a length prefixed value when it spans lines
+pub fn journal_fields(fields: &[(&str, String)]) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    for (key, value) in fields {
+        if value.contains('\n') {
+            datagram.extend_from_slice(key.as_bytes());
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            datagram.extend_from_slice(value.as_bytes());
+            datagram.push(b'\n');
+        }
+        else {
+            datagram.extend_from_slice(format!("{}={}\n", key, value).as_bytes());
+        }
+    }
+    datagram
+}
+
+/// Structured fields of an action record - `journalctl ACTION=remove` finds every removed file
+pub fn record_fields(record: &ActionRecord) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("FILE", record.file.display().to_string()),
+        ("ACTION", record.action.as_str().to_string()),
+        ("BYTES", record.bytes.to_string()),
+    ];
+    if let Some(archive) = &record.archive {
+        fields.push(("ARCHIVE", archive.display().to_string()));
+    }
+    if let Some(reason) = &record.reason {
+        fields.push(("REASON", reason.clone()));
+    }
+    fields
+}
+
+/// Journal writer - entries go to stderr when the journal socket is not there or refuses them
+#[derive(Debug)]
+pub struct Journal {
+    socket: Option<UnixDatagram>,
+}
+
+impl Journal {
+    /// Connect to journald when it is running
+    pub fn open() -> Journal {
+        Journal::connect(Path::new(JOURNAL_SOCKET))
+    }
+
+    pub fn connect(socket_path: &Path) -> Journal {
+        let socket = UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(socket_path).map(|_| socket))
+            .ok();
+        Journal { socket }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+
+    /// Log a message with extra structured fields
+    pub fn send(&self, priority: u8, message: &str, extra_fields: &[(&str, String)]) {
+        if let Some(socket) = &self.socket {
+            let mut fields = vec![
+                ("MESSAGE", message.to_string()),
+                ("PRIORITY", priority.to_string()),
+                ("SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER.to_string()),
+            ];
+            fields.extend(extra_fields.iter().cloned());
+            if socket.send(&journal_fields(&fields)).is_ok() {
+                return;
+            }
+        }
+        let _ = writeln!(io::stderr(), "{} {}", Local::now().format("%Y-%m-%dT%H:%M:%S%:z"), message);
+    }
+
+    /// Log an action record - skipped files are warnings
+    pub fn record(&self, message_prefix: &str, record: &ActionRecord, extra_fields: &[(&str, String)]) {
+        let priority = match record.action {
+            Action::Skipped => PRIORITY_WARNING,
+            _ => PRIORITY_INFO,
+        };
+        let mut fields = record_fields(record);
+        fields.extend(extra_fields.iter().cloned());
+        self.send(priority, &format!("{}{}", message_prefix, record), &fields);
+    }
+}
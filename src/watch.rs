@@ -0,0 +1,221 @@
+//! Size triggered rotation for daemon targets
+//!
+//! Watched directories report writes and new files through inotify. A file is looked at once it has been written
+//! to for the debounce period, and rotated when it is past its target's `max_size` - at most once per cooldown, so a
+//! runaway logger cannot turn the daemon into a compression loop
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::{ARCHIVE_EXTENSIONS, get_file_extension};
+
+/// Quiet time after the first write to a file before its size is checked
+pub const SIZE_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Watch mask - writes, new files and files moved in
+const WATCH_MASK: u32 = libc::IN_MODIFY | libc::IN_CREATE | libc::IN_MOVED_TO;
+
+/// What a watched directory reported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// A file was written to, created or moved in
+    File(PathBuf),
+    /// A subdirectory was created or moved in
+    Directory(PathBuf),
+    /// The kernel queue overflowed and events were lost - every watched file needs a look
+    Overflow,
+}
+
+/// inotify watches on a set of directories
+#[derive(Debug)]
+pub struct DirectoryWatcher {
+    fd: OwnedFd,
+    directories: HashMap<i32, PathBuf>,
+}
+
+impl DirectoryWatcher {
+    pub fn new() -> anyhow::Result<DirectoryWatcher> {
+        // SAFETY: plain syscall, the descriptor is owned below
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(anyhow::anyhow!("Unable to initialise inotify: {}", io::Error::last_os_error()));
+        }
+        Ok(DirectoryWatcher {
+            // SAFETY: fd is a freshly opened descriptor nothing else owns
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            directories: HashMap::new(),
+        })
+    }
+
+    /// Watch a directory for writes and new entries - watching the same directory twice is a no-op
+    pub fn watch(&mut self, directory: &Path) -> anyhow::Result<()> {
+        let path = CString::new(directory.as_os_str().as_bytes())?;
+        // SAFETY: path is a valid NUL terminated string for the duration of the call
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(anyhow::anyhow!("Unable to watch '{}': {}", directory.display(), io::Error::last_os_error()));
+        }
+        self.directories.insert(wd, directory.to_path_buf());
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for events - a signal cuts the wait short with no events
+    pub fn wait(&mut self, timeout: Duration) -> anyhow::Result<Vec<WatchEvent>> {
+        let mut poll_fd = libc::pollfd { fd: self.fd.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as libc::c_int;
+        // SAFETY: poll_fd is a single valid pollfd
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+        if ready < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                return Ok(Vec::new());
+            }
+            return Err(anyhow::anyhow!("Unable to wait for inotify events: {}", error));
+        }
+        self.read_events()
+    }
+
+    /// Drain every queued event without blocking
+    pub fn read_events(&mut self) -> anyhow::Result<Vec<WatchEvent>> {
+        let mut events = Vec::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            // SAFETY: buffer is valid for writes of its full length
+            let length = unsafe { libc::read(self.fd.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len()) };
+            if length < 0 {
+                let error = io::Error::last_os_error();
+                match error.kind() {
+                    io::ErrorKind::WouldBlock => break,
+                    io::ErrorKind::Interrupted => continue,
+                    _ => return Err(anyhow::anyhow!("Unable to read inotify events: {}", error)),
+                }
+            }
+            if length == 0 {
+                break;
+            }
+            events.extend(self.parse_events(&buffer[..length as usize]));
+        }
+        Ok(events)
+    }
+
+    /// Split a read buffer into events - each is a `struct inotify_event` followed by `len` bytes of padded name
+    fn parse_events(&mut self, buffer: &[u8]) -> Vec<WatchEvent> {
+        let header_length = std::mem::size_of::<libc::inotify_event>();
+        let field = |offset: usize| u32::from_ne_bytes(buffer[offset..offset + 4].try_into().unwrap());
+        let mut events = Vec::new();
+        let mut offset = 0;
+
+        while offset + header_length <= buffer.len() {
+            let wd = field(offset) as i32;
+            let mask = field(offset + 4);
+            let name_length = field(offset + 12) as usize;
+            let name = &buffer[offset + header_length..offset + header_length + name_length];
+            offset += header_length + name_length;
+
+            if mask & libc::IN_Q_OVERFLOW != 0 {
+                events.push(WatchEvent::Overflow);
+                continue;
+            }
+            if mask & libc::IN_IGNORED != 0 {
+                self.directories.remove(&wd);
+                continue;
+            }
+            let name = name.split(|byte| *byte == 0).next().unwrap_or_default();
+            let Some(directory) = self.directories.get(&wd) else { continue };
+            if name.is_empty() {
+                continue;
+            }
+            let path = directory.join(std::ffi::OsStr::from_bytes(name));
+            let event = match mask & libc::IN_ISDIR != 0 {
+                true => WatchEvent::Directory(path),
+                false => WatchEvent::File(path),
+            };
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+        events
+    }
+}
+
+/// Debounce and rate limit for size checks of one target
+#[derive(Debug, Clone)]
+pub struct SizeTrigger {
+    pub max_size: u64,
+    pub debounce: Duration,
+    /// Shortest time between two size triggered rotations of the same file
+    pub cooldown: Duration,
+    /// Files written to since their last check, with the first write
+    pending: HashMap<PathBuf, Instant>,
+    last_rotated: HashMap<PathBuf, Instant>,
+}
+
+impl SizeTrigger {
+    pub fn new(max_size: u64, debounce: Duration, cooldown: Duration) -> SizeTrigger {
+        SizeTrigger { max_size, debounce, cooldown, pending: HashMap::new(), last_rotated: HashMap::new() }
+    }
+
+    /// Note a write to `file` - the first write starts the debounce, later ones do not push it back
+    pub fn notify(&mut self, file: &Path, now: Instant) {
+        self.pending.entry(file.to_path_buf()).or_insert(now);
+    }
+
+    /// Take the files whose debounce is over and whose cooldown has passed - files still cooling down stay pending
+    pub fn due(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut due: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(file, first_write)| {
+                now.duration_since(**first_write) >= self.debounce
+                    && self
+                        .last_rotated
+                        .get(*file)
+                        .is_none_or(|rotated| now.duration_since(*rotated) >= self.cooldown)
+            })
+            .map(|(file, _)| file.clone())
+            .collect();
+        due.sort();
+        for file in &due {
+            self.pending.remove(file);
+        }
+        due
+    }
+
+    /// Of the due files, the ones past `max_size`
+    pub fn oversized(&self, files: Vec<PathBuf>) -> Vec<PathBuf> {
+        files
+            .into_iter()
+            .filter(|file| is_active_log(file) && fs::metadata(file).is_ok_and(|metadata| metadata.is_file() && metadata.len() >= self.max_size))
+            .collect()
+    }
+
+    pub fn rotated(&mut self, file: &Path, now: Instant) {
+        self.last_rotated.insert(file.to_path_buf(), now);
+    }
+
+    /// Earliest time a pending file becomes due - None when nothing is pending
+    pub fn next_due(&self) -> Option<Instant> {
+        self.pending
+            .iter()
+            .map(|(file, first_write)| {
+                let debounced = *first_write + self.debounce;
+                match self.last_rotated.get(file) {
+                    Some(rotated) => debounced.max(*rotated + self.cooldown),
+                    None => debounced,
+                }
+            })
+            .min()
+    }
+}
+
+/// Whether a file is a live log the bucketing would archive - not an archive, temp file, manifest or `app.log.1`
+pub fn is_active_log(file: &Path) -> bool {
+    let extension = get_file_extension(file.to_str().unwrap_or_default());
+    !ARCHIVE_EXTENSIONS.contains(&extension.as_str()) && extension != "unknown"
+}
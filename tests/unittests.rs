@@ -1,6 +1,8 @@
 use logrotate::{
     ArchiveType,
     archive_remove_truncate_file_bucketing,
+    redacted_digest,
+    bucket_file,
     gather_files_from_directory,
     get_file_mtime_diff,
     test_add,
@@ -10,10 +12,64 @@ use logrotate::{
     zip_file,
     remove_file,
     get_date,
+    split_archive_name,
+    count_retention_candidates,
+    apply_count_retention,
+    apply_disk_budget,
+    parse_size,
+    plan_disk_budget,
+    check_free_space,
+    archive_with_guard,
+    Action,
+    FreeSpaceGuard,
+    FreeSpaceReserve,
+    LowSpaceAction,
+    RunOptions,
+    temp_path_for,
+    write_atomically,
+    cleanup_stray_temp_files,
+    archive_selection_and_process,
+    archive_entry_digests,
+    file_digest,
+    verify_archive_against_source,
+    parse_mode,
+    remove_retained_file,
+    get_file_extension,
+    archive_file,
+    unique_entry_name,
+    compress_file,
+    NamingScheme,
+    run_jobs,
+    actual_run,
+    ActionRecord,
+    plan_run,
+    archive_families,
+};
+use logrotate::catalog::{parse_log_timestamp, read_catalog, scan_content};
+use logrotate::rules::{glob_regex, RuleSet};
+use logrotate::bundle::{bundle_files, bundle_key, BundleGroup};
+use logrotate::crypto::decrypt_archive;
+use logrotate::daemon::{rotate_oversized, run_target, Scheduler};
+use logrotate::watch::{DirectoryWatcher, SizeTrigger, WatchEvent};
+use logrotate::policy::parse_policy;
+use logrotate::restore::{restore_archive, restore_destination, RestoreOptions};
+use logrotate::schedule::Schedule;
+use logrotate::search::{archives_in_window, search_archives, SearchEvent};
+use logrotate::state::{read_state, record_run, RunSummary, KEPT_RUNS};
+use logrotate::syslog::{format_record, SyslogEndpoint, SyslogFacility, SyslogSender};
+use logrotate::systemd::{journal_fields, notify_socket, Journal};
+use logrotate::parallel::{ParallelCompression, GZIP_BLOCK_SIZE};
+use logrotate::redact::{builtin_rules, luhn_valid, RedactionCounts, RedactionRule, Redactor};
+use logrotate::manifest::{
+    verify_chain,
+    read_manifest,
+    manifest_path,
+    ManifestEvent,
 };
 
 use std::fs;
 use std::path;
+use std::os::unix::fs::PermissionsExt;
 
 
 /// Helper function to create test files
@@ -36,7 +92,7 @@ mod tests {
         // let right_hand_operand = vec![path::PathBuf::from("./tests/test_log_dir/test_log_file.log")];
         // old test - assert_eq!(testing_operand, right_hand_operand);
 
-        assert!(testing_operand.len() >= 1);
+        assert!(!testing_operand.is_empty());
     }
 
 
@@ -148,7 +204,7 @@ mod tests {
         let test_file_path = "./tests/test_log_dir/test_log_file.log";
 
         // Truncate test file to reset mtime to the current day for comparison
-        let file = fs::File::create(test_file_path.to_string()).unwrap();
+        let file = fs::File::create(test_file_path).unwrap();
         file.set_len(0).unwrap();
 
         let diff_testing_operand = get_file_mtime_diff(test_file_path).unwrap();
@@ -211,10 +267,15 @@ mod tests {
     #[test]
     fn test_truncate_file() {
         let test_file_path = "./tests/test_log_dir/test_log_file.log";
-        truncate_file(test_file_path);
+        truncate_file(test_file_path).unwrap();
         
-        let file_size = fs::metadata(test_file_path.to_string()).unwrap().len();
+        let file_size = fs::metadata(test_file_path).unwrap().len();
         assert_eq!(file_size, 0);
+
+        // A file removed meanwhile is an error, not a panic, and is not created again
+        let missing_path = "./tests/test_log_dir/missing_file.log";
+        assert!(truncate_file(missing_path).is_err());
+        assert!(!path::Path::new(missing_path).exists());
     }
     
     #[test]
@@ -222,12 +283,12 @@ mod tests {
         let test_file_path = "./tests/test_log_dir/test_log_file.log";
         let test_new_tar_file= test_file_path.to_string() + "_" + &get_date() + ".tar.gz";
         
-        tar_gunzip_file(&test_file_path, ArchiveType::TarGunzip).expect("Error tar-ing file");
+        tar_gunzip_file(test_file_path, ArchiveType::TarGunzip).expect("Error tar-ing file");
         
         assert!(path::Path::new(test_new_tar_file.as_str()).exists());
         
         // Clean up the test tar.gz file that is created
-        fs::remove_file(test_new_tar_file.to_string()).unwrap();
+        fs::remove_file(&test_new_tar_file).unwrap();
     }
 
     #[test]
@@ -235,10 +296,10 @@ mod tests {
         let test_file_path = "./tests/test_log_dir/test_log_file.log";
         let test_new_tar_file= test_file_path.to_string() + "_" + &get_date() + ".tar";
 
-        tar_file(&test_file_path, ArchiveType::Tar).expect("Error tar-ing file");
+        tar_file(test_file_path, ArchiveType::Tar).expect("Error tar-ing file");
 
         assert!(path::Path::new(test_new_tar_file.as_str()).exists());
-        fs::remove_file(test_new_tar_file.to_string()).unwrap();
+        fs::remove_file(&test_new_tar_file).unwrap();
     }
 
     #[test]
@@ -246,12 +307,12 @@ mod tests {
         let test_file_path = "./tests/test_log_dir/test_log_file.log";
         let test_new_zip_file= test_file_path.to_string() + "_" + &get_date() + ".zip";
 
-        zip_file(&test_file_path, ArchiveType::Zip).expect("Error zipping file");
+        zip_file(test_file_path, ArchiveType::Zip).expect("Error zipping file");
 
         assert!(path::Path::new(test_new_zip_file.as_str()).exists());
 
         // Clean up the test zip file that is created
-        fs::remove_file(test_new_zip_file.to_string()).unwrap();
+        fs::remove_file(&test_new_zip_file).unwrap();
     }
 
     #[test]
@@ -270,13 +331,1318 @@ mod tests {
     #[test]
     fn test_remove_file_process() {
         let test_file_path = "./tests/test_log_dir/test_log_file_2.log";
-        fs::File::create(test_file_path.to_string()).unwrap();
+        fs::File::create(test_file_path).unwrap();
         assert!(path::Path::new(test_file_path).exists());
 
         remove_file(test_file_path);
         assert!(!path::Path::new(test_file_path).exists());
     }
 
+    #[test]
+    fn test_split_archive_name() {
+        let (family, date, index) = split_archive_name("./logs/app.log_2025_09_26.tar.gz").unwrap();
+        assert_eq!(family, "./logs/app.log");
+        assert_eq!(date, chrono::NaiveDate::from_ymd_opt(2025, 9, 26));
+        assert_eq!(index, None);
+
+        let (family, date, index) = split_archive_name("./logs/app.log.zip").unwrap();
+        assert_eq!(family, "./logs/app.log");
+        assert_eq!(date, None);
+        assert_eq!(index, None);
+
+        assert_eq!(split_archive_name("./logs/app.log.12.gz").unwrap(), ("./logs/app.log".to_string(), None, Some(12)));
+        assert!(split_archive_name("./logs/app.log").is_none());
+    }
+
+    #[test]
+    fn test_numbered_archive_order() {
+        let test_dir = "./tests/test_numbered_order_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        // Touched out of order - the rotation number decides, not mtime
+        let now = std::time::SystemTime::now();
+        let archives: Vec<path::PathBuf> = (1..=3).map(|number| path::Path::new(test_dir).join(format!("app.log.{}.gz", number))).collect();
+        for (offset, archive) in archives.iter().enumerate() {
+            fs::write(archive, "rotated").unwrap();
+            fs::File::options().write(true).open(archive).unwrap().set_modified(now - std::time::Duration::from_secs(3600 * (3 - offset as u64))).unwrap();
+        }
+
+        let families = archive_families(&archives);
+        let ordered: Vec<&path::PathBuf> = families.values().next().unwrap().iter().map(|listing| &listing.archive).collect();
+        assert_eq!(ordered, archives.iter().collect::<Vec<_>>());
+        assert_eq!(families.values().next().unwrap()[2].index, Some(3));
+        assert_eq!(count_retention_candidates(&archives, 1), archives[1..].to_vec());
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_count_retention_candidates() {
+        let test_dir = "./tests/test_keep_count_dir";
+        fs::create_dir_all(test_dir).unwrap();
+
+        let archives = [
+            "app.log_2025_09_24.tar.gz",
+            "app.log_2025_09_25.tar.gz",
+            "app.log_2025_09_26.tar.gz",
+            "other.log_2025_09_20.zip",
+        ];
+        let mut file_list: Vec<path::PathBuf> = archives
+            .iter()
+            .map(|name| path::Path::new(test_dir).join(name))
+            .collect();
+        for file in &file_list {
+            fs::File::create(file).unwrap();
+        }
+        // Active logs are never candidates
+        file_list.push(path::Path::new(test_dir).join("app.log"));
+
+        let candidates = count_retention_candidates(&file_list, 2);
+        assert_eq!(candidates, vec![path::Path::new(test_dir).join("app.log_2025_09_24.tar.gz")]);
+
+        let candidates = count_retention_candidates(&file_list, 0);
+        assert_eq!(candidates.len(), 4);
+
+        // A removal that fails is reported and the pass goes on with the next candidate
+        fs::write(path::Path::new(test_dir).join(".logrotate-catalog"), "not json\n").unwrap();
+        let report = apply_count_retention(test_dir, 1, &RunOptions::default(), false).unwrap();
+        assert_eq!(report.len(), 2);
+        assert!(report.iter().all(|record| record.action == Action::Skipped && record.reason.as_ref().unwrap().contains("catalog")));
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_size("500M").unwrap(), 500 * 1024 * 1024);
+        assert_eq!(parse_size("2gb").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert!(parse_size("ten").is_err());
+        assert!(parse_size("10X").is_err());
+    }
+
+    #[test]
+    fn test_plan_disk_budget() {
+        let test_dir = "./tests/test_disk_budget_dir";
+        fs::create_dir_all(test_dir).unwrap();
+
+        let files = [
+            ("app.log", 400),
+            ("small.log", 100),
+            ("app.log_2025_09_24.tar.gz", 100),
+            ("app.log_2025_09_25.tar.gz", 100),
+        ];
+        let file_list: Vec<path::PathBuf> = files
+            .iter()
+            .map(|(name, size)| {
+                let file = path::Path::new(test_dir).join(name);
+                fs::write(&file, vec![b'x'; *size]).unwrap();
+                file
+            })
+            .collect();
+
+        // Removing the oldest archive is enough
+        let plan = plan_disk_budget(&file_list, 650, false);
+        assert_eq!(plan.usage_before, 700);
+        assert_eq!(plan.usage_after, 600);
+        assert_eq!(plan.remove, vec![path::Path::new(test_dir).join("app.log_2025_09_24.tar.gz")]);
+        assert!(plan.force_rotate.is_empty());
+
+        // Every archive goes, then the largest active file is rotated
+        let plan = plan_disk_budget(&file_list, 300, true);
+        assert_eq!(plan.remove.len(), 2);
+        assert_eq!(plan.force_rotate, vec![path::Path::new(test_dir).join("app.log")]);
+        assert_eq!(plan.usage_after, 100);
+
+        // Without force-rotate the budget can stay exceeded
+        let plan = plan_disk_budget(&file_list, 300, false);
+        assert_eq!(plan.usage_after, 500);
+
+        // Failed removals are reported without ending the pass
+        fs::write(path::Path::new(test_dir).join(".logrotate-catalog"), "not json\n").unwrap();
+        let report = apply_disk_budget(test_dir, 300, false, ArchiveType::TarGunzip, &RunOptions::default(), false).unwrap();
+        assert_eq!(report.len(), 2);
+        assert!(report.iter().all(|record| record.action == Action::Skipped && record.reason.is_some()));
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_free_space_reserve_parse() {
+        assert_eq!("10%".parse::<FreeSpaceReserve>().unwrap(), FreeSpaceReserve::Percent(10));
+        assert_eq!("1G".parse::<FreeSpaceReserve>().unwrap(), FreeSpaceReserve::Bytes(1 << 30));
+        assert!("101%".parse::<FreeSpaceReserve>().is_err());
+        assert!("lots".parse::<FreeSpaceReserve>().is_err());
+    }
+
+    #[test]
+    fn test_check_free_space() {
+        let test_file_path = creat_test_file("space");
+
+        assert!(check_free_space(&test_file_path, FreeSpaceReserve::Bytes(0)).unwrap().is_none());
+        assert!(check_free_space(&test_file_path, FreeSpaceReserve::Percent(100)).unwrap().is_some());
+
+        fs::remove_file(test_file_path).unwrap();
+    }
+
+    #[test]
+    fn test_archive_with_guard_low_space() {
+        let test_file_path = "./tests/test_log_dir/test_guard_file.log";
+        fs::write(test_file_path, "keep me").unwrap();
+
+        let skip_options = RunOptions {
+            free_space_guard: Some(FreeSpaceGuard {
+                reserve: FreeSpaceReserve::Percent(100),
+                on_low_space: LowSpaceAction::Skip,
+            }),
+            ..Default::default()
+        };
+        let record = archive_with_guard(path::Path::new(test_file_path), ArchiveType::Tar, &skip_options);
+        assert_eq!(record.action, Action::Skipped);
+        assert!(record.reason.unwrap().contains("low disk space"));
+        assert!(record.archive.is_none());
+        assert_eq!(fs::read_to_string(test_file_path).unwrap(), "keep me");
+
+        let emergency_options = RunOptions {
+            free_space_guard: Some(FreeSpaceGuard {
+                reserve: FreeSpaceReserve::Percent(100),
+                on_low_space: LowSpaceAction::Emergency,
+            }),
+            ..Default::default()
+        };
+        let record = archive_with_guard(path::Path::new(test_file_path), ArchiveType::Tar, &emergency_options);
+        assert_eq!(record.action, Action::Truncate);
+        assert_eq!(fs::metadata(test_file_path).unwrap().len(), 0);
+
+        fs::remove_file(test_file_path).unwrap();
+    }
+
+    #[test]
+    fn test_non_utf8_file_name() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let test_dir = path::Path::new("./tests/test_non_utf8_dir");
+        fs::create_dir_all(test_dir).unwrap();
+        let file = test_dir.join(OsStr::from_bytes(b"app\xff.log"));
+        fs::write(&file, "line\n").unwrap();
+
+        // Skipped with a reason rather than panicking, and left untouched
+        let record = archive_with_guard(&file, ArchiveType::Tar, &RunOptions::default());
+        assert_eq!(record.action, Action::Skipped);
+        assert!(record.reason.unwrap().contains("not valid UTF-8"));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "line\n");
+
+        let planned = plan_run(std::slice::from_ref(&file), 7, &ArchiveType::Tar, &RunOptions::default());
+        assert_eq!(planned[0].action, Action::Skipped);
+
+        truncate_file(&file).unwrap();
+        assert_eq!(fs::metadata(&file).unwrap().len(), 0);
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_temp_path_for() {
+        let temp_path = temp_path_for(path::Path::new("./logs/app.log_2025_09_26.zip"));
+        assert_eq!(temp_path, path::PathBuf::from("./logs/.app.log_2025_09_26.zip.logrotate-tmp"));
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let test_dir = "./tests/test_atomic_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let final_path = path::Path::new(test_dir).join("archive.tar");
+
+        // A failing writer leaves neither the archive nor its temp file behind
+        let result = write_atomically(&final_path, |_| Err(anyhow::anyhow!("disk full")));
+        assert!(result.is_err());
+        assert!(!final_path.exists());
+        assert!(!temp_path_for(&final_path).exists());
+
+        write_atomically(&final_path, |mut file| {
+            std::io::Write::write_all(&mut file, b"archive")?;
+            Ok(file)
+        }).unwrap();
+        assert_eq!(fs::read_to_string(&final_path).unwrap(), "archive");
+        assert!(!temp_path_for(&final_path).exists());
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_cleanup_stray_temp_files() {
+        let test_dir = "./tests/test_stray_temp_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let stray_file = path::Path::new(test_dir).join(".app.log_2025_09_26.tar.gz.logrotate-tmp");
+        let live_file = path::Path::new(test_dir).join("app.log");
+        fs::File::create(&stray_file).unwrap();
+        fs::File::create(&live_file).unwrap();
+
+        let removed = cleanup_stray_temp_files(test_dir).unwrap();
+        assert_eq!(removed, vec![stray_file.clone()]);
+        assert!(!stray_file.exists());
+        assert!(live_file.exists());
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_archive_verification() {
+        let test_dir = "./tests/test_verify_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let source_path = path::Path::new(test_dir).join("verify.log");
+        let verify_options = RunOptions { verify: true, ..Default::default() };
+
+        for archive_type in [ArchiveType::Tar, ArchiveType::TarGunzip, ArchiveType::Zip] {
+            fs::write(&source_path, "line one\nline two\n").unwrap();
+            let (source_length, source_sha256) = file_digest(&source_path).unwrap();
+
+            let archive = archive_selection_and_process(source_path.to_str().unwrap(), archive_type, &verify_options).unwrap();
+            let archive = path::Path::new(&archive);
+
+            assert!(verify_archive_against_source(archive, source_length, &source_sha256).is_ok());
+            assert!(verify_archive_against_source(archive, source_length + 1, &source_sha256).is_err());
+            assert_eq!(fs::metadata(&source_path).unwrap().len(), 0);
+
+            // Chop the archive short of its entry data (a tar header is 512 bytes) - it must no longer decode
+            let archive_bytes = fs::read(archive).unwrap();
+            fs::write(archive, &archive_bytes[..(archive_bytes.len() / 2).min(515)]).unwrap();
+            assert!(archive_entry_digests(archive).is_err());
+        }
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("0640").unwrap(), 0o640);
+        assert_eq!(parse_mode("600").unwrap(), 0o600);
+        assert!(parse_mode("0899").is_err());
+        assert!(parse_mode("17777").is_err());
+    }
+
+    #[test]
+    fn test_archives_preserve_metadata() {
+        let test_dir = "./tests/test_metadata_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let source_path = path::Path::new(test_dir).join("metadata.log");
+        let options = RunOptions { create_mode: Some(0o640), ..Default::default() };
+
+        fs::write(&source_path, "metadata").unwrap();
+        fs::set_permissions(&source_path, fs::Permissions::from_mode(0o600)).unwrap();
+        let source_mtime = fs::metadata(&source_path).unwrap().modified().unwrap()
+            .duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let xattr_supported = xattr::set(&source_path, "user.logrotate", b"kept").is_ok();
+
+        let tar_archive = archive_selection_and_process(source_path.to_str().unwrap(), ArchiveType::Tar, &options).unwrap();
+        let mut tar_reader = tar::Archive::new(fs::File::open(&tar_archive).unwrap());
+        let mut entry = tar_reader.entries().unwrap().next().unwrap().unwrap();
+        assert_eq!(entry.header().mode().unwrap() & 0o7777, 0o600);
+        assert_eq!(entry.header().mtime().unwrap(), source_mtime);
+        if xattr_supported {
+            let pax_extensions = entry.pax_extensions().unwrap().unwrap();
+            let xattr_record = pax_extensions
+                .map(|extension| extension.unwrap())
+                .find(|extension| extension.key().unwrap() == "SCHILY.xattr.user.logrotate")
+                .unwrap();
+            assert_eq!(xattr_record.value_bytes(), b"kept");
+        }
+
+        // The archive takes the source mode and the truncated live file gets the create mode
+        assert_eq!(fs::metadata(&tar_archive).unwrap().permissions().mode() & 0o7777, 0o600);
+        assert_eq!(fs::metadata(&source_path).unwrap().permissions().mode() & 0o7777, 0o640);
+
+        fs::write(&source_path, "metadata").unwrap();
+        let archive_options = RunOptions { archive_mode: Some(0o644), ..Default::default() };
+        let zip_archive = archive_selection_and_process(source_path.to_str().unwrap(), ArchiveType::Zip, &archive_options).unwrap();
+        let mut zip_reader = zip::ZipArchive::new(fs::File::open(&zip_archive).unwrap()).unwrap();
+        assert_eq!(zip_reader.by_index(0).unwrap().unix_mode().unwrap() & 0o7777, 0o640);
+        assert_eq!(fs::metadata(&zip_archive).unwrap().permissions().mode() & 0o7777, 0o644);
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_manifest_hash_chain() {
+        let test_dir = "./tests/test_manifest_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let options = RunOptions { manifest: true, ..Default::default() };
+
+        let mut archives = Vec::new();
+        for name in ["first.log", "second.log", "third.log"] {
+            let source_path = path::Path::new(test_dir).join(name);
+            fs::write(&source_path, name).unwrap();
+            let archive = archive_selection_and_process(source_path.to_str().unwrap(), ArchiveType::TarGunzip, &options).unwrap();
+            archives.push(path::PathBuf::from(archive));
+        }
+
+        let entries = read_manifest(path::Path::new(test_dir)).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].prev_hash, entries[0].entry_hash);
+        assert!(verify_chain(path::Path::new(test_dir)).unwrap().is_intact());
+
+        // Retention removals are recorded and keep the chain intact
+        remove_retained_file(&archives[0], &options).unwrap();
+        let entries = read_manifest(path::Path::new(test_dir)).unwrap();
+        assert_eq!(entries.last().unwrap().event, ManifestEvent::Remove);
+        assert!(verify_chain(path::Path::new(test_dir)).unwrap().is_intact());
+
+        // Deleting an archive behind the manifest's back is caught
+        fs::remove_file(&archives[1]).unwrap();
+        let report = verify_chain(path::Path::new(test_dir)).unwrap();
+        assert!(!report.is_intact());
+        assert!(report.problems[0].contains("deleted"));
+
+        // So are reordered manifest lines
+        fs::write(&archives[1], "restored").unwrap();
+        let manifest = fs::read_to_string(manifest_path(path::Path::new(test_dir))).unwrap();
+        let mut lines: Vec<&str> = manifest.lines().collect();
+        lines.swap(0, 1);
+        fs::write(manifest_path(path::Path::new(test_dir)), lines.join("\n")).unwrap();
+        let report = verify_chain(path::Path::new(test_dir)).unwrap();
+        assert!(report.problems.iter().any(|problem| problem.contains("reordered")));
+        assert!(report.problems.iter().any(|problem| problem.contains("modified")));
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_archives() {
+        use age::secrecy::ExposeSecret;
+
+        let test_dir = "./tests/test_encrypt_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let identity = age::x25519::Identity::generate();
+        let identity_file = path::Path::new(test_dir).join("identity.txt");
+        fs::write(&identity_file, identity.to_string().expose_secret()).unwrap();
+
+        let options = RunOptions { recipients: vec![identity.to_public()], ..Default::default() };
+        let source_path = path::Path::new(test_dir).join("secret.log");
+
+        for archive_type in [ArchiveType::Tar, ArchiveType::TarGunzip, ArchiveType::Zip] {
+            fs::write(&source_path, "card 4111 1111 1111 1111").unwrap();
+            let (source_length, source_sha256) = file_digest(&source_path).unwrap();
+
+            let archive = archive_selection_and_process(source_path.to_str().unwrap(), archive_type, &options).unwrap();
+            assert!(archive.ends_with(".age"));
+            assert_eq!(get_file_extension(&archive), "age");
+            assert_eq!(split_archive_name(&archive).unwrap().0, source_path.to_str().unwrap());
+            assert!(!fs::read(&archive).unwrap().windows(4).any(|window| window == b"4111"));
+
+            let decrypted = decrypt_archive(path::Path::new(&archive), &identity_file, None).unwrap();
+            assert!(verify_archive_against_source(&decrypted, source_length, &source_sha256).is_ok());
+
+            // Never overwrites
+            assert!(decrypt_archive(path::Path::new(&archive), &identity_file, None).is_err());
+        }
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_redact_line() {
+        let mut rules = vec![RedactionRule::parse("user-id=USR-\\d+").unwrap()];
+        rules.extend(builtin_rules());
+        let redactor = Redactor::new(rules);
+        let mut counts = RedactionCounts::new();
+
+        let redacted = redactor.redact_line(b"USR-42 bob@example.com 10.0.0.1 4111-1111-1111-1111 order 1234567890123\n", &mut counts);
+        assert_eq!(
+            String::from_utf8(redacted).unwrap(),
+            "[REDACTED:user-id] [REDACTED:email] [REDACTED:ipv4] [REDACTED:credit-card] order 1234567890123\n",
+        );
+        assert_eq!(counts.get("email"), Some(&1));
+        assert_eq!(counts.get("credit-card"), Some(&1));
+        assert_eq!(counts.get("user-id"), Some(&1));
+
+        assert!(luhn_valid(b"4111 1111 1111 1111"));
+        assert!(!luhn_valid(b"1234567890123"));
+        assert!(RedactionRule::parse("missing-equals").is_err());
+        assert!(RedactionRule::parse("bad=(").is_err());
+    }
+
+    #[test]
+    fn test_redacted_archives() {
+        let test_dir = "./tests/test_redact_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let source_path = path::Path::new(test_dir).join("pii.log");
+        let options = RunOptions {
+            redactor: Some(Redactor::new(builtin_rules())),
+            verify: true,
+            ..Default::default()
+        };
+
+        for archive_type in [ArchiveType::TarGunzip, ArchiveType::Zip] {
+            fs::write(&source_path, "login alice@example.com\nfrom 192.168.1.20\nplain line\n").unwrap();
+            let (source_length, (redacted_length, _)) = redacted_digest(&source_path, options.redactor.as_ref().unwrap()).unwrap();
+            assert_eq!((source_length, redacted_length), (53, 55));
+
+            let outcome = archive_file(source_path.to_str().unwrap(), archive_type.clone(), &options).unwrap();
+            assert_eq!(outcome.redactions.get("email"), Some(&1));
+            assert_eq!(outcome.redactions.get("ipv4"), Some(&1));
+
+            let archived = match archive_type {
+                ArchiveType::Zip => {
+                    let mut zip_reader = zip::ZipArchive::new(fs::File::open(&outcome.archive).unwrap()).unwrap();
+                    std::io::read_to_string(zip_reader.by_index(0).unwrap()).unwrap()
+                }
+                _ => {
+                    let decoder = flate2::read::GzDecoder::new(fs::File::open(&outcome.archive).unwrap());
+                    let mut tar_reader = tar::Archive::new(decoder);
+                    std::io::read_to_string(tar_reader.entries().unwrap().next().unwrap().unwrap()).unwrap()
+                }
+            };
+            assert_eq!(archived, "login [REDACTED:email]\nfrom [REDACTED:ipv4]\nplain line\n");
+        }
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_bundle_files() {
+        let test_dir = path::Path::new("./tests/test_bundle_dir");
+        fs::create_dir_all(test_dir.join("nginx")).unwrap();
+        let files = vec![test_dir.join("app.log"), test_dir.join("audit.json"), test_dir.join("nginx/access.log")];
+        for file in &files {
+            fs::write(file, "line\n").unwrap();
+        }
+
+        assert_eq!(bundle_key(test_dir, &files[0], BundleGroup::None), "");
+        assert_eq!(bundle_key(test_dir, &files[1], BundleGroup::FileType), "json");
+        assert_eq!(bundle_key(test_dir, &files[0], BundleGroup::Subdirectory), "");
+        assert_eq!(bundle_key(test_dir, &files[2], BundleGroup::Subdirectory), "nginx");
+
+        let options = RunOptions { verify: true, manifest: true, ..Default::default() };
+        let records = bundle_files(test_dir, &files, BundleGroup::None, ArchiveType::TarGunzip, &options);
+        let bundle_path = test_dir.join(format!("logrotate-bundle_{}.tar.gz", get_date()));
+        assert_eq!(records.len(), 3);
+        for (record, file) in records.iter().zip(&files) {
+            assert_eq!(&record.file, file);
+            assert_eq!(record.action, Action::Archive);
+            assert_eq!(record.archive.as_deref(), Some(bundle_path.as_path()));
+            assert_eq!(fs::metadata(file).unwrap().len(), 0);
+        }
+
+        let mut names: Vec<String> = archive_entry_digests(&bundle_path).unwrap().into_iter().map(|entry| entry.name).collect();
+        names.sort();
+        assert_eq!(names, vec!["app.log", "audit.json", "nginx/access.log"]);
+        assert_eq!(read_manifest(test_dir).unwrap().len(), 1);
+        // Retention sees the bundle as one archive of its own family
+        assert_eq!(split_archive_name(bundle_path.to_str().unwrap()).unwrap().0, test_dir.join("logrotate-bundle").to_str().unwrap());
+
+        for file in &files {
+            fs::write(file, "line\n").unwrap();
+        }
+        let records = bundle_files(test_dir, &files, BundleGroup::FileType, ArchiveType::Zip, &RunOptions::default());
+        assert_eq!(records[0].archive, records[2].archive);
+        assert_eq!(records[1].archive, Some(test_dir.join(format!("logrotate-bundle-json_{}.zip", get_date()))));
+
+        // A second run the same day adds to the bundle instead of replacing it
+        for file in &files {
+            fs::write(file, "again\n").unwrap();
+        }
+        let records = bundle_files(test_dir, &files, BundleGroup::None, ArchiveType::TarGunzip, &options);
+        assert!(records.iter().all(|record| record.action == Action::Archive));
+        let mut names: Vec<String> = archive_entry_digests(&bundle_path).unwrap().into_iter().map(|entry| entry.name).collect();
+        names.sort();
+        assert_eq!(names, vec!["app.log", "app.log.1", "audit.json", "audit.json.1", "nginx/access.log", "nginx/access.log.1"]);
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_append_to_existing_archive() {
+        assert_eq!(unique_entry_name("app.log", &[]), "app.log");
+        assert_eq!(unique_entry_name("app.log", &["app.log".to_string(), "app.log.1".to_string()]), "app.log.2");
+
+        let test_dir = "./tests/test_append_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let source_path = path::Path::new(test_dir).join("hourly.log");
+        let options = RunOptions { append: true, verify: true, manifest: true, ..Default::default() };
+
+        for archive_type in [ArchiveType::Tar, ArchiveType::TarGunzip, ArchiveType::Zip] {
+            let mut archive_path = String::new();
+            for hour in 0..3 {
+                fs::write(&source_path, format!("hour {}\n", hour)).unwrap();
+                archive_path = archive_selection_and_process(source_path.to_str().unwrap(), archive_type.clone(), &options).unwrap();
+            }
+
+            let digests = archive_entry_digests(path::Path::new(&archive_path)).unwrap();
+            let names: Vec<&str> = digests.iter().map(|entry| entry.name.rsplit('/').next().unwrap()).collect();
+            assert_eq!(names, vec!["hourly.log", "hourly.log.1", "hourly.log.2"]);
+            assert!(digests.iter().all(|entry| entry.length == 7));
+        }
+        assert!(verify_chain(path::Path::new(test_dir)).unwrap().is_intact());
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_single_file_compression() {
+        let test_dir = "./tests/test_compress_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let source_path = path::Path::new(test_dir).join("app.log");
+        let mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+
+        for (archive_type, extension) in [(ArchiveType::Gzip, "gz"), (ArchiveType::Zstd, "zst"), (ArchiveType::Xz, "xz")] {
+            fs::write(&source_path, "first line\nsecond line\n").unwrap();
+            fs::File::options().write(true).open(&source_path).unwrap().set_modified(mtime).unwrap();
+            let (length, sha256) = file_digest(&source_path).unwrap();
+
+            compress_file(source_path.to_str().unwrap(), archive_type.clone()).unwrap();
+            let archive_path = format!("{}_{}.{}", source_path.to_str().unwrap(), get_date(), extension);
+            assert_eq!(ArchiveType::from_archive_path(&archive_path), Some(archive_type));
+            assert_eq!(split_archive_name(&archive_path).unwrap().0, source_path.to_str().unwrap());
+            assert_eq!(get_file_extension(&archive_path), extension);
+
+            let digests = archive_entry_digests(path::Path::new(&archive_path)).unwrap();
+            assert_eq!(digests.len(), 1);
+            assert_eq!(digests[0].name, "app.log");
+            verify_archive_against_source(path::Path::new(&archive_path), length, &sha256).unwrap();
+        }
+
+        // The gzip header carries the original name and mtime
+        let gzip_path = format!("{}_{}.gz", source_path.to_str().unwrap(), get_date());
+        let mut decoder = flate2::read::GzDecoder::new(fs::File::open(&gzip_path).unwrap());
+        std::io::read_to_string(&mut decoder).unwrap();
+        let header = decoder.header().unwrap();
+        assert_eq!(header.filename(), Some("app.log".as_bytes()));
+        assert_eq!(header.mtime(), 1_700_000_000);
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_delayed_compression() {
+        let test_dir = "./tests/test_delay_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let source_path = path::Path::new(test_dir).join("daemon.log");
+        let source = source_path.to_str().unwrap();
+        let options = RunOptions {
+            naming: NamingScheme::Numbered,
+            delay_compress: true,
+            verify: true,
+            manifest: true,
+            ..Default::default()
+        };
+
+        for run in 1..=3 {
+            fs::write(&source_path, format!("run {}\n", run)).unwrap();
+            let archive = archive_selection_and_process(source, ArchiveType::Gzip, &options).unwrap();
+            assert_eq!(archive, format!("{}.1", source));
+            assert_eq!(fs::read_to_string(&archive).unwrap(), format!("run {}\n", run));
+            assert_eq!(fs::metadata(&source_path).unwrap().len(), 0);
+        }
+
+        // Run 3 is plain, runs 2 and 1 were compressed on the following runs and shifted up
+        let decompress = |number: u32| {
+            let archive = fs::File::open(format!("{}.{}.gz", source, number)).unwrap();
+            std::io::read_to_string(flate2::read::GzDecoder::new(archive)).unwrap()
+        };
+        assert_eq!(decompress(2), "run 2\n");
+        assert_eq!(decompress(3), "run 1\n");
+        assert!(!path::Path::new(&format!("{}.4.gz", source)).exists());
+        assert_eq!(split_archive_name(&format!("{}.3.gz", source)).unwrap(), (source.to_string(), None, Some(3)));
+        assert!(verify_chain(path::Path::new(test_dir)).unwrap().is_intact());
+
+        // Without delaycompress the new rotation is compressed straight into .1
+        let options = RunOptions { naming: NamingScheme::Numbered, ..Default::default() };
+        fs::write(&source_path, "run 4\n").unwrap();
+        assert_eq!(archive_selection_and_process(source, ArchiveType::Gzip, &options).unwrap(), format!("{}.1.gz", source));
+        assert_eq!(decompress(4), "run 1\n");
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_parallel_run() {
+        let squares = run_jobs(&(0..100u64).collect::<Vec<u64>>(), 8, |number| number * number);
+        assert_eq!(squares, (0..100u64).map(|number| number * number).collect::<Vec<u64>>());
+
+        let test_dir = "./tests/test_jobs_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let file_list: Vec<path::PathBuf> = (0..24)
+            .map(|index| {
+                let file = path::Path::new(test_dir).join(format!("worker_{}.log", index));
+                fs::write(&file, format!("entry {}\n", index).repeat(100)).unwrap();
+                file
+            })
+            .collect();
+
+        let options = RunOptions { jobs: 4, verify: true, manifest: true, ..Default::default() };
+        let report = actual_run(file_list.clone(), 7, ArchiveType::TarGunzip, &options);
+        assert_eq!(report.iter().map(|record| record.file.clone()).collect::<Vec<path::PathBuf>>(), file_list);
+        assert!(report.iter().all(|record| record.action == Action::Archive && record.archive.is_some()));
+
+        // Manifest appends from every worker end up in one unbroken chain
+        let chain = verify_chain(path::Path::new(test_dir)).unwrap();
+        assert_eq!(chain.entries, 24);
+        assert!(chain.is_intact());
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_schedule() {
+        use chrono::{Local, TimeZone, Timelike, Datelike};
+        let start = Local.with_ymd_and_hms(2025, 9, 26, 10, 7, 30).unwrap();
+
+        let daily: Schedule = "daily at 03:00".parse().unwrap();
+        let next = daily.next_after(start);
+        assert_eq!((next.day(), next.hour(), next.minute()), (27, 3, 0));
+
+        let quarterly: Schedule = "*/15 * * * *".parse().unwrap();
+        assert_eq!(quarterly.next_after(start).minute(), 15);
+        let weekdays: Schedule = "30 9 * * 1-5".parse().unwrap();
+        // 26 September 2025 is a Friday
+        assert_eq!(weekdays.next_after(start).day(), 29);
+        // A day of month starting with `*` leaves the day of week alone in deciding, rather than either matching
+        let mondays: Schedule = "0 0 */2 * 1".parse().unwrap();
+        assert_eq!(mondays.next_after(start).day(), 29);
+
+        let interval: Schedule = "every 15m".parse().unwrap();
+        assert_eq!(interval.next_after(start) - start, chrono::Duration::minutes(15));
+
+        for invalid in ["sometimes", "every 0m", "every 5x", "61 * * * *", "* * * *", "daily at 25:00"] {
+            assert!(invalid.parse::<Schedule>().is_err(), "{} should not parse", invalid);
+        }
+    }
+
+    #[test]
+    fn test_daemon_policy() {
+        let policy = r#"
+            [[target]]
+            name = "app"
+            directory = "./tests/test_daemon_dir"
+            schedule = "every 1h"
+            archive_method = "zstd"
+            keep_count = 2
+            verify = true
+
+            [[target]]
+            name = "nginx"
+            directory = "/var/log/nginx"
+            schedule = "daily"
+            archive_method = "tar-gunzip"
+        "#;
+        let targets = parse_policy(policy).unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].archive_type, ArchiveType::Zstd);
+        assert!(targets[0].options.verify);
+        assert_eq!(targets[1].keep_days, 7);
+
+        let duplicate = policy.replace("\"nginx\"", "\"app\"");
+        assert!(parse_policy(&duplicate).is_err());
+        assert!(parse_policy(&policy.replace("tar-gunzip", "rar")).is_err());
+        assert!(parse_policy(&policy.replace("verify = true", "verfy = true")).is_err());
+        assert!(parse_policy("").is_err());
+
+        // Several runs a day would replace the day's dated archive - numbered naming unless appending
+        assert_eq!(targets[0].options.naming, NamingScheme::Numbered);
+        assert_eq!(targets[1].options.naming, NamingScheme::Date);
+        let hourly = "[[target]]\nname = \"hourly\"\ndirectory = \"/var/log/app\"\nschedule = \"hourly\"\narchive_method = \"tar-gunzip\"\n";
+        assert_eq!(parse_policy(hourly).unwrap()[0].options.naming, NamingScheme::Numbered);
+        assert!(parse_policy(&format!("{}naming = \"date\"\n", hourly)).is_err());
+        assert!(parse_policy(&format!("{}bundle = true\n", hourly)).is_err());
+        assert_eq!(parse_policy(&format!("{}append = true\n", hourly)).unwrap()[0].options.naming, NamingScheme::Date);
+        assert!("0 0,12 * * *".parse::<Schedule>().unwrap().fires_more_than_daily());
+        assert!(!"daily at 03:00".parse::<Schedule>().unwrap().fires_more_than_daily());
+
+        // Combinations refused at run time are refused when the policy loads
+        let daily = "[[target]]\nname = \"daily\"\ndirectory = \"/var/log/app\"\nschedule = \"daily\"\n";
+        let recipients = format!("recipients = [\"{}\"]\n", age::x25519::Identity::generate().to_public());
+        assert!(parse_policy(&format!("{}archive_method = \"zip\"\nappend = true\n", daily)).is_ok());
+        assert!(parse_policy(&format!("{}archive_method = \"zip\"\nappend = true\n{}", daily, recipients)).is_err());
+        assert!(parse_policy(&format!("{}archive_method = \"zstd\"\nappend = true\n", daily)).is_err());
+        assert!(parse_policy(&format!("{}archive_method = \"gzip\"\nbundle = true\n", daily)).is_err());
+        assert!(parse_policy(&format!("{}archive_method = \"zip\"\nappend = true\nnaming = \"numbered\"\n", daily)).is_err());
+        assert!(parse_policy(&format!("{}archive_method = \"gzip\"\ndelay_compress = true\n", daily)).is_ok());
+        assert!(parse_policy(&format!("{}archive_method = \"tar\"\ndelay_compress = true\nbundle = true\n", daily)).is_err());
+        assert!(parse_policy(&format!("{}archive_method = \"gzip\"\ndelay_compress = true\n{}", daily, recipients)).is_err());
+        assert!(parse_policy(&format!("{}archive_method = \"gzip\"\ndelay_compress = true\nnaming = \"date\"\n", daily)).is_err());
+
+        // Reloading keeps the next run of unchanged targets and schedules new ones from the reload
+        let now = chrono::Local::now();
+        let mut scheduler = Scheduler::new(targets.clone(), now);
+        assert!(scheduler.due(now).is_empty());
+        let later = now + chrono::Duration::hours(2);
+        assert_eq!(scheduler.due(later), vec![0]);
+        scheduler.mark_run(0, later);
+        assert_eq!(scheduler.entries[0].next_run, later + chrono::Duration::hours(1));
+        scheduler.reload(parse_policy(&policy.replace("every 1h", "every 2h")).unwrap(), later);
+        assert_eq!(scheduler.entries[0].next_run, later + chrono::Duration::hours(2));
+        assert_eq!(scheduler.entries[1].next_run, targets[1].schedule.next_after(now));
+
+        let test_dir = "./tests/test_daemon_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let log_path = path::Path::new(test_dir).join("service.log");
+        fs::write(&log_path, "started\n").unwrap();
+        let report = run_target(&targets[0]).unwrap();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].action, Action::Archive);
+        assert!(report[0].archive.as_ref().unwrap().exists());
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), 0);
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_size_triggered_rotation() {
+        use std::time::{Duration, Instant};
+        let test_dir = "./tests/test_watch_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let mut watcher = DirectoryWatcher::new().unwrap();
+        watcher.watch(path::Path::new(test_dir)).unwrap();
+
+        // New files are reported by the directory watch, writes and subdirectories too
+        let log_path = path::Path::new(test_dir).join("runaway.log");
+        fs::write(&log_path, "x".repeat(4096)).unwrap();
+        fs::create_dir_all(path::Path::new(test_dir).join("nested")).unwrap();
+        let events = watcher.wait(Duration::from_secs(1)).unwrap();
+        assert!(events.contains(&WatchEvent::File(log_path.clone())));
+        assert!(events.contains(&WatchEvent::Directory(path::Path::new(test_dir).join("nested"))));
+
+        // Debounce from the first write, then at most one rotation per cooldown
+        let start = Instant::now();
+        let mut trigger = SizeTrigger::new(1024, Duration::from_secs(2), Duration::from_secs(60));
+        trigger.notify(&log_path, start);
+        trigger.notify(&log_path, start + Duration::from_secs(1));
+        assert!(trigger.due(start + Duration::from_secs(1)).is_empty());
+        let due = trigger.due(start + Duration::from_secs(2));
+        assert_eq!(trigger.oversized(due), vec![log_path.clone()]);
+        trigger.rotated(&log_path, start + Duration::from_secs(2));
+        trigger.notify(&log_path, start + Duration::from_secs(3));
+        assert!(trigger.due(start + Duration::from_secs(30)).is_empty());
+        assert_eq!(trigger.next_due(), Some(start + Duration::from_secs(62)));
+        assert_eq!(trigger.due(start + Duration::from_secs(62)), vec![log_path.clone()]);
+
+        let policy = format!(
+            "[[target]]\nname = \"runaway\"\ndirectory = \"{}\"\nschedule = \"daily\"\narchive_method = \"gzip\"\nmax_size = \"1K\"\n",
+            test_dir,
+        );
+        let target = parse_policy(&policy).unwrap().remove(0);
+        assert_eq!(target.max_size, Some(1024));
+        assert_eq!(target.rotate_cooldown, Duration::from_secs(60));
+        assert_eq!(target.options.naming, NamingScheme::Numbered);
+        assert!(parse_policy(&(policy.clone() + "naming = \"date\"\n")).is_err());
+        let report = rotate_oversized(&target, std::slice::from_ref(&log_path)).unwrap();
+        assert_eq!(report[0].action, Action::Archive);
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), 0);
+        // The archive written next to the log is not something to rotate again
+        assert!(trigger.oversized(vec![report[0].archive.clone().unwrap()]).is_empty());
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_systemd_sockets() {
+        use std::os::unix::net::UnixDatagram;
+        let test_dir = "./tests/test_systemd_dir";
+        fs::create_dir_all(test_dir).unwrap();
+
+        let notify_path = path::Path::new(test_dir).join("notify.sock");
+        let service_manager = UnixDatagram::bind(&notify_path).unwrap();
+        notify_socket(notify_path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buffer = [0u8; 4096];
+        let length = service_manager.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..length], b"READY=1");
+
+        // Values spanning lines are length prefixed
+        let encoded = journal_fields(&[("MESSAGE", "one\ntwo".to_string()), ("ACTION", "remove".to_string())]);
+        assert_eq!(encoded, [b"MESSAGE\n".as_slice(), &7u64.to_le_bytes(), b"one\ntwo\nACTION=remove\n"].concat());
+
+        let journal_path = path::Path::new(test_dir).join("journal.sock");
+        let journald = UnixDatagram::bind(&journal_path).unwrap();
+        let journal = Journal::connect(&journal_path);
+        assert!(journal.is_connected());
+        let log_path = path::Path::new(test_dir).join("old.log.gz");
+        fs::write(&log_path, "0123456789").unwrap();
+        let record = ActionRecord::new(&log_path, Action::Remove);
+        journal.record("[app] ", &record, &[("TARGET", "app".to_string())]);
+
+        let length = journald.recv(&mut buffer).unwrap();
+        let entry = String::from_utf8_lossy(&buffer[..length]).to_string();
+        let fields: Vec<&str> = entry.lines().collect();
+        assert!(fields.contains(&"ACTION=remove"));
+        assert!(fields.contains(&"BYTES=10"));
+        assert!(fields.contains(&"PRIORITY=6"));
+        assert!(fields.contains(&"TARGET=app"));
+        assert!(fields.contains(&format!("FILE={}", log_path.display()).as_str()));
+        assert!(fields.contains(&"SYSLOG_IDENTIFIER=logrotate"));
+
+        // No socket - entries fall back to stderr
+        assert!(!Journal::connect(&path::Path::new(test_dir).join("missing.sock")).is_connected());
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_syslog_forwarding() {
+        use std::os::unix::net::UnixDatagram;
+        use std::sync::Arc;
+        let test_dir = "./tests/test_syslog_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let socket_path = fs::canonicalize(test_dir).unwrap().join("log.sock");
+        let syslogd = UnixDatagram::bind(&socket_path).unwrap();
+
+        assert_eq!("udp://logs.example.com:514".parse::<SyslogEndpoint>().unwrap(), SyslogEndpoint::Udp("logs.example.com:514".to_string()));
+        assert_eq!(socket_path.to_str().unwrap().parse::<SyslogEndpoint>().unwrap(), SyslogEndpoint::Unix(socket_path.clone()));
+        assert!("dev/log".parse::<SyslogEndpoint>().is_err());
+        assert!("udp://logs.example.com".parse::<SyslogEndpoint>().is_err());
+
+        // Parsing a policy validates the endpoint without connecting to it
+        let policy = format!(
+            "[[target]]\nname = \"app\"\ndirectory = \"{}\"\nschedule = \"daily\"\narchive_method = \"gzip\"\nsyslog = true\nsyslog_endpoint = \"{}\"\n",
+            test_dir,
+            socket_path.with_file_name("missing.sock").display(),
+        );
+        let mut target = parse_policy(&policy).unwrap().remove(0);
+        assert!(target.syslog.is_some() && target.options.syslog.is_none());
+        assert!(target.connect_syslog().is_err());
+        assert!(parse_policy(&policy.replace("syslog_endpoint = \"/", "syslog_endpoint = \"")).is_err());
+        let mut target = parse_policy(&policy.replace("missing.sock", "log.sock")).unwrap().remove(0);
+        target.connect_syslog().unwrap();
+        assert!(target.options.syslog.is_some());
+
+        let log_path = path::Path::new(test_dir).join("forwarded.log");
+        fs::write(&log_path, "line\n").unwrap();
+        let sender = SyslogSender::connect(&SyslogEndpoint::Unix(socket_path.clone()), SyslogFacility::Local0).unwrap();
+        let options = RunOptions { syslog: Some(Arc::new(sender)), ..Default::default() };
+        let report = actual_run(vec![log_path.clone()], 7, ArchiveType::Gzip, &options);
+        assert_eq!(report[0].action, Action::Archive);
+
+        let mut buffer = [0u8; 4096];
+        let length = syslogd.recv(&mut buffer).unwrap();
+        let message = String::from_utf8_lossy(&buffer[..length]).to_string();
+        // local0 (16) * 8 + informational (6)
+        assert!(message.starts_with("<134>1 "), "{}", message);
+        assert!(message.contains(" logrotate "));
+        assert!(message.contains(&format!("[logrotate@32473 file=\"{}\" action=\"archive\" extension=\"log\" bytes=\"5\"", log_path.display())));
+        assert!(message.contains(&format!("archive=\"{}\"]", report[0].archive.as_ref().unwrap().display())));
+
+        // Removals are notices, special characters are escaped and unchanged files are not forwarded
+        let mut record = ActionRecord::new(&log_path, Action::Remove);
+        record.reason = Some("quota \"exceeded\" [disk]".to_string());
+        let message = format_record(&record, SyslogFacility::Daemon, "host").unwrap();
+        assert!(message.starts_with("<29>1 "));
+        assert!(message.contains(r#"reason="quota \"exceeded\" [disk\]""#));
+        assert!(format_record(&ActionRecord::new(&log_path, Action::Unchanged), SyslogFacility::Daemon, "host").is_none());
+
+        let collector = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let endpoint = SyslogEndpoint::Udp(collector.local_addr().unwrap().to_string());
+        SyslogSender::connect(&endpoint, SyslogFacility::Daemon).unwrap().send_record(&record).unwrap();
+        let length = collector.recv(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..length]).contains(" remove [logrotate@32473 "));
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_plan_and_state() {
+        let test_dir = "./tests/test_plan_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let log_path = path::Path::new(test_dir).join("planned.log");
+        fs::write(&log_path, "planned\n").unwrap();
+        let old_archive = path::Path::new(test_dir).join("planned.log_2025_01_01.tar.gz");
+        fs::write(&old_archive, "old").unwrap();
+        let ten_days_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(10 * 86400);
+        fs::File::options().write(true).open(&old_archive).unwrap().set_modified(ten_days_ago).unwrap();
+
+        // Planning leaves every file as it is
+        let plan = plan_run(&[log_path.clone(), old_archive.clone()], 7, &ArchiveType::TarGunzip, &RunOptions::default());
+        assert_eq!(plan[0].action, Action::Archive);
+        assert_eq!(plan[0].archive.as_ref().unwrap().to_str().unwrap(), logrotate::archive_path_for(log_path.to_str().unwrap(), &ArchiveType::TarGunzip));
+        assert_eq!(plan[0].method.as_deref(), Some("tar.gz"));
+        assert_eq!(plan[1].action, Action::Remove);
+        assert_eq!(plan[1].rule, "keep-days");
+        assert!(old_archive.exists());
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json[1]["action"], "remove");
+        assert_eq!(json[0]["bytes"], 8);
+
+        let families = archive_families(&[log_path.clone(), old_archive.clone()]);
+        assert_eq!(families.len(), 1);
+        assert_eq!(families[log_path.to_str().unwrap()][0].archive, old_archive);
+
+        let started = chrono::Local::now();
+        let report = actual_run(vec![log_path.clone(), old_archive.clone()], 7, ArchiveType::TarGunzip, &RunOptions::default());
+        let summary = RunSummary::from_report(started, chrono::Local::now(), &report);
+        assert_eq!((summary.archived, summary.removed, summary.bytes_archived, summary.bytes_removed), (1, 1, 8, 3));
+        for _ in 0..KEPT_RUNS + 2 {
+            record_run(path::Path::new(test_dir), summary.clone()).unwrap();
+        }
+        let state = read_state(path::Path::new(test_dir)).unwrap();
+        assert_eq!(state.runs.len(), KEPT_RUNS);
+        assert_eq!(state.runs[0], summary);
+        assert!(read_state(path::Path::new("./tests")).unwrap().runs.is_empty());
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_restore_archive() {
+        let test_dir = "./tests/test_restore_dir";
+        let target_dir = path::Path::new(test_dir).join("restored");
+        fs::create_dir_all(test_dir).unwrap();
+        let yesterday = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_758_000_000);
+        for archive_type in [ArchiveType::Tar, ArchiveType::TarGunzip, ArchiveType::Zip] {
+            let log_path = path::Path::new(test_dir).join("restored.log");
+            fs::write(&log_path, "yesterday\n").unwrap();
+            fs::set_permissions(&log_path, fs::Permissions::from_mode(0o640)).unwrap();
+            fs::File::options().write(true).open(&log_path).unwrap().set_modified(yesterday).unwrap();
+            let archive = archive_file(log_path.to_str().unwrap(), archive_type, &RunOptions::default()).unwrap().archive;
+            fs::remove_file(&log_path).unwrap();
+
+            assert_eq!(restore_archive(path::Path::new(&archive), &RestoreOptions::default()).unwrap(), vec![log_path.clone()]);
+            assert_eq!(fs::read_to_string(&log_path).unwrap(), "yesterday\n");
+            let metadata = fs::metadata(&log_path).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+            assert_eq!(metadata.modified().unwrap(), yesterday);
+
+            // The restored file is only overwritten with force
+            fs::write(&log_path, "today\n").unwrap();
+            assert!(restore_archive(path::Path::new(&archive), &RestoreOptions::default()).is_err());
+            assert_eq!(fs::read_to_string(&log_path).unwrap(), "today\n");
+            let force = RestoreOptions { force: true, ..Default::default() };
+            restore_archive(path::Path::new(&archive), &force).unwrap();
+            assert_eq!(fs::read_to_string(&log_path).unwrap(), "yesterday\n");
+
+            let elsewhere = RestoreOptions { target_dir: Some(target_dir.clone()), force: false };
+            assert_eq!(restore_archive(path::Path::new(&archive), &elsewhere).unwrap(), vec![target_dir.join("restored.log")]);
+            fs::remove_file(target_dir.join("restored.log")).unwrap();
+            fs::remove_file(&archive).unwrap();
+        }
+
+        // Absolute zip entries stay next to the archive
+        let archive = path::Path::new(test_dir).join("foreign.zip");
+        let destination = restore_destination(&archive, "/etc/cron.d/restored", &ArchiveType::Zip, &RestoreOptions::default()).unwrap();
+        assert_eq!(destination, path::Path::new(test_dir).join("restored"));
+        assert!(restore_destination(&archive, "/etc/cron.d/../x", &ArchiveType::Zip, &RestoreOptions::default()).is_err());
+        assert!(restore_destination(&archive, "/etc/cron.d/x", &ArchiveType::Tar, &RestoreOptions::default()).is_err());
+
+        // Zip bundles keep their subdirectories, absolute entries in them are still reduced to the file name
+        let files = vec![path::Path::new(test_dir).join("a/app.log"), path::Path::new(test_dir).join("b/app.log")];
+        for file in &files {
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, file.to_str().unwrap()).unwrap();
+        }
+        let records = bundle_files(path::Path::new(test_dir), &files, BundleGroup::None, ArchiveType::Zip, &RunOptions::default());
+        let bundle = records[0].archive.clone().unwrap();
+        assert_eq!(restore_archive(&bundle, &RestoreOptions { target_dir: Some(target_dir.clone()), force: false }).unwrap(),
+                   vec![target_dir.join("a/app.log"), target_dir.join("b/app.log")]);
+        assert_eq!(fs::read_to_string(target_dir.join("b/app.log")).unwrap(), files[1].to_str().unwrap());
+        assert_eq!(restore_destination(&bundle, "/etc/cron.d/x", &ArchiveType::Zip, &RestoreOptions::default()).unwrap(), path::Path::new(test_dir).join("x"));
+
+        // Links are refused rather than written as empty files
+        for entry_type in [tar::EntryType::Symlink, tar::EntryType::Link] {
+            let archive = path::Path::new(test_dir).join("links.log_2025_09_26.tar");
+            let mut tar_builder = tar::Builder::new(fs::File::create(&archive).unwrap());
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(0);
+            header.set_mode(0o644);
+            tar_builder.append_link(&mut header, "passwd.log", "/etc/passwd").unwrap();
+            tar_builder.finish().unwrap();
+            drop(tar_builder);
+
+            assert!(restore_archive(&archive, &RestoreOptions::default()).is_err());
+            assert!(fs::symlink_metadata(path::Path::new(test_dir).join("passwd.log")).is_err());
+            fs::remove_file(&archive).unwrap();
+        }
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_search_archives() {
+        let test_dir = "./tests/test_search_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let log_path = path::Path::new(test_dir).join("search.log");
+        let mut archives = Vec::new();
+        for (date, archive_type) in [("2025_09_20", ArchiveType::TarGunzip), ("2025_09_23", ArchiveType::Zip), ("2025_09_26", ArchiveType::Zstd)] {
+            fs::write(&log_path, format!("request-1 ok\nrequest-42 on {}\r\n", date)).unwrap();
+            let archive = archive_file(log_path.to_str().unwrap(), archive_type, &RunOptions::default()).unwrap().archive;
+            let dated = archive.replace(&get_date(), date);
+            fs::rename(&archive, &dated).unwrap();
+            archives.push(path::PathBuf::from(dated));
+        }
+        fs::write(&log_path, "request-42 live\n").unwrap();
+        let mut file_list = archives.clone();
+        file_list.push(log_path.clone());
+
+        let date = |day| chrono::NaiveDate::from_ymd_opt(2025, 9, day);
+        assert_eq!(archives_in_window(&file_list, None, None), archives);
+        // Lines up to the 23rd are in the archives of the 20th and 23rd, lines after the 23rd only in the one of the 26th
+        assert_eq!(archives_in_window(&file_list, date(24), None), archives[2..]);
+        assert_eq!(archives_in_window(&file_list, None, date(21)), archives[..2]);
+        assert_eq!(archives_in_window(&file_list, date(21), date(23)), archives[1..]);
+
+        let pattern = regex::bytes::Regex::new("request-42").unwrap();
+        let mut lines: Vec<String> = Vec::new();
+        search_archives(&file_list, &pattern, None, None, 3, |event| match event {
+            SearchEvent::Match(found) => lines.push(found.to_string()),
+            SearchEvent::Failed(archive, error) => panic!("{}: {:#}", archive.display(), error),
+        });
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], format!("{}:search.log:request-42 on 2025_09_20", archives[0].display()));
+        assert!(lines[1].starts_with(&format!("{}:", archives[1].display())));
+        assert!(lines[1].ends_with(":request-42 on 2025_09_23"));
+        assert_eq!(lines[2], format!("{}:search.log:request-42 on 2025_09_26", archives[2].display()));
+
+        // More matches than an archive may queue ahead - order holds and a broken archive does not stop the rest
+        let bulk_path = path::Path::new(test_dir).join("bulk.log");
+        let mut bulk_archives = Vec::new();
+        for date in ["2025_09_20", "2025_09_21"] {
+            fs::write(&bulk_path, (0..3000).map(|line| format!("request-42 {} {}\n", date, line)).collect::<String>()).unwrap();
+            let archive = archive_file(bulk_path.to_str().unwrap(), ArchiveType::Gzip, &RunOptions::default()).unwrap().archive;
+            let dated = archive.replace(&get_date(), date);
+            fs::rename(&archive, &dated).unwrap();
+            bulk_archives.push(path::PathBuf::from(dated));
+        }
+        let broken = path::Path::new(test_dir).join("bulk.log_2025_09_22.gz");
+        fs::write(&broken, "not gzip").unwrap();
+        bulk_archives.push(broken.clone());
+        let (mut lines, mut failed) = (Vec::new(), Vec::new());
+        search_archives(&bulk_archives, &pattern, None, None, 3, |event| match event {
+            SearchEvent::Match(found) => lines.push(found.line),
+            SearchEvent::Failed(archive, _) => failed.push(archive),
+        });
+        assert_eq!(lines.len(), 6000);
+        assert_eq!((lines[0].as_str(), lines[2999].as_str(), lines[3000].as_str()), ("request-42 2025_09_20 0", "request-42 2025_09_20 2999", "request-42 2025_09_21 0"));
+        assert_eq!(failed, vec![broken]);
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_archive_catalog() {
+        let parsed = |line| parse_log_timestamp(line, 2025).map(|time| time.to_rfc3339());
+        assert_eq!(parsed("2025-09-26T03:00:00.123Z INFO up").as_deref(), Some("2025-09-26T03:00:00+00:00"));
+        assert_eq!(parsed("[2025-09-26 03:00:00,5 -0130] WARN").as_deref(), Some("2025-09-26T03:00:00-01:30"));
+        assert_eq!(parsed("10.0.0.1 - - [26/Sep/2025:03:00:00 +0200] \"GET /\"").as_deref(), Some("2025-09-26T03:00:00+02:00"));
+        assert!(parse_log_timestamp("Sep 26 03:00:00 web1 sshd[42]: accepted", 2025).is_some());
+        assert_eq!(parsed("no timestamp here"), None);
+
+        let content = "header\n2025-09-20T10:00:00Z first\nplain\n2025-09-21T10:00:00Z last\ntrace\n";
+        let stats = scan_content(content.as_bytes(), 2025).unwrap();
+        assert_eq!((stats.bytes, stats.lines), (content.len() as u64, 5));
+        assert_eq!(stats.first_timestamp.unwrap().to_rfc3339(), "2025-09-20T10:00:00+00:00");
+        assert_eq!(stats.last_timestamp.unwrap().to_rfc3339(), "2025-09-21T10:00:00+00:00");
+
+        let test_dir = "./tests/test_catalog_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let log_path = path::Path::new(test_dir).join("catalog.log");
+        let numbered = RunOptions { naming: NamingScheme::Numbered, ..Default::default() };
+        fs::write(&log_path, content).unwrap();
+        let first = archive_file(log_path.to_str().unwrap(), ArchiveType::TarGunzip, &numbered).unwrap().archive;
+        let catalog = read_catalog(path::Path::new(test_dir)).unwrap();
+        assert_eq!(catalog.len(), 1);
+        assert_eq!(catalog[0].archive, "catalog.log.1.tar.gz");
+        assert_eq!(catalog[0].sources, vec![fs::canonicalize(&log_path).unwrap().display().to_string()]);
+        assert_eq!((catalog[0].lines, catalog[0].original_bytes), (5, content.len() as u64));
+        assert_eq!(catalog[0].archive_bytes, fs::metadata(&first).unwrap().len());
+        assert_eq!(catalog[0].sha256, file_digest(path::Path::new(&first)).unwrap().1);
+        assert_eq!(catalog[0].last_timestamp.as_deref(), Some("2025-09-21T10:00:00+00:00"));
+
+        // The next rotation shifts the first archive to .2, and the catalog follows it
+        fs::write(&log_path, "2025-09-27T10:00:00Z later\n").unwrap();
+        archive_file(log_path.to_str().unwrap(), ArchiveType::TarGunzip, &numbered).unwrap();
+        let catalog = read_catalog(path::Path::new(test_dir)).unwrap();
+        let names: Vec<&str> = catalog.iter().map(|entry| entry.archive.as_str()).collect();
+        assert_eq!(names, vec!["catalog.log.2.tar.gz", "catalog.log.1.tar.gz"]);
+        assert_eq!(catalog[0].lines, 5);
+
+        // Undated archives are placed by the catalog's time range, and ordered by it for retention
+        let file_list = gather_files_from_directory(test_dir).unwrap();
+        let date = |day| chrono::NaiveDate::from_ymd_opt(2025, 9, day);
+        let newer = path::Path::new(test_dir).join("catalog.log.1.tar.gz");
+        let older = path::Path::new(test_dir).join("catalog.log.2.tar.gz");
+        assert_eq!(archives_in_window(&file_list, date(25), None), vec![newer.clone()]);
+        assert_eq!(archives_in_window(&file_list, None, date(22)), vec![older.clone()]);
+        assert_eq!(count_retention_candidates(&file_list, 1), vec![older.clone()]);
+
+        remove_retained_file(&older, &RunOptions::default()).unwrap();
+        assert_eq!(read_catalog(path::Path::new(test_dir)).unwrap().len(), 1);
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_explain_bucketing() {
+        let test_dir = "./tests/test_explain_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let log_path = path::Path::new(test_dir).join("explained.log");
+        fs::write(&log_path, "explained\n").unwrap();
+        let one_day_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(86400 + 60);
+        fs::File::options().write(true).open(&log_path).unwrap().set_modified(one_day_ago).unwrap();
+
+        // A day old with keep-days 1 is too old to archive and too young to truncate
+        let explanation = bucket_file(&log_path, 1).unwrap();
+        assert_eq!((explanation.age_days, explanation.action, explanation.decided_by.clone()), (1, Action::Unchanged, None));
+        let archive_rule = &explanation.rules[1];
+        assert_eq!(archive_rule.name, "archive-recent-logs");
+        assert!(!archive_rule.matched());
+        assert_eq!((archive_rule.conditions[0].actual.as_str(), archive_rule.conditions[0].passed), ("1 < 1", false));
+        assert!(explanation.to_string().ends_with("Result: Unchanged | Decided By: no rule matched"));
+
+        let explanation = bucket_file(&log_path, 7).unwrap();
+        assert_eq!((explanation.action, explanation.decided_by.as_deref()), (Action::Archive, Some("archive-recent-logs")));
+        assert_eq!(archive_remove_truncate_file_bucketing(log_path.to_str().unwrap(), 7).unwrap(), 0);
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_rules_engine() {
+        let test_dir = "./tests/test_rules_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let old_log = path::Path::new(test_dir).join("old.log");
+        let kept_log = path::Path::new(test_dir).join("app-1.log");
+        let empty_log = path::Path::new(test_dir).join("empty.log");
+        let old_archive = path::Path::new(test_dir).join("old.log_2025_01_01.tar.gz");
+        fs::write(&old_log, "old\n").unwrap();
+        fs::write(&kept_log, "kept\n").unwrap();
+        fs::write(&empty_log, "").unwrap();
+        fs::write(&old_archive, "old").unwrap();
+        let three_days_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3 * 86400);
+        let ten_days_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(10 * 86400);
+        for (file, modified) in [(&old_log, three_days_ago), (&kept_log, three_days_ago), (&empty_log, three_days_ago), (&old_archive, ten_days_ago)] {
+            fs::File::options().write(true).open(file).unwrap().set_modified(modified).unwrap();
+        }
+
+        // Archive old logs, remove expired archives - the first matching rule wins
+        let owner = std::os::unix::fs::MetadataExt::uid(&fs::metadata(&old_log).unwrap()).to_string();
+        let rules = RuleSet::parse("custom", &format!(r#"
+            [[rule]]
+            name = "expire-archives"
+            action = "remove"
+            age = "> keep-days"
+            file_type = "archive"
+
+            [[rule]]
+            name = "keep-app"
+            action = "unchanged"
+            glob = "app-*.log"
+
+            [[rule]]
+            name = "archive-old-logs"
+            action = "archive"
+            age = ">= 2"
+            size = "> 0"
+            not_file_type = ["archive", "unknown"]
+            owner = "{}"
+        "#, owner)).unwrap();
+        assert_eq!(rules.decide(&old_archive, 7).unwrap(), Action::Remove);
+        assert_eq!(rules.decide(&kept_log, 7).unwrap(), Action::Unchanged);
+        assert_eq!(rules.decide(&old_log, 7).unwrap(), Action::Archive);
+        // No rule matches an empty log
+        let explanation = rules.explain(&empty_log, 7).unwrap();
+        assert_eq!((explanation.action, explanation.decided_by), (Action::Unchanged, None));
+        assert_eq!(explanation.rule_set, "custom");
+
+        assert!(RuleSet::parse("bad", "[[rule]]\nname = \"x\"\naction = \"shred\"").is_err());
+        assert!(RuleSet::parse("bad", "[[rule]]\nname = \"x\"\naction = \"remove\"\nage = \"about 3\"").is_err());
+        assert!(RuleSet::load("no-such-preset-or-file").is_err());
+        assert!(glob_regex("app-*.log").unwrap().is_match("app-12.log"));
+        assert!(!glob_regex("app-?.log").unwrap().is_match("app-12.log"));
+        assert!(glob_regex("app[0-9].log").unwrap().is_match("app7.log"));
+        assert!(glob_regex("app[log").is_err());
+        // Class contents follow POSIX globs, not regex set operations
+        assert!(glob_regex("app[]].log").unwrap().is_match("app].log"));
+        assert!(glob_regex("app[!]].log").unwrap().is_match("app1.log"));
+        assert!(!glob_regex("app[!]].log").unwrap().is_match("app].log"));
+        assert!(glob_regex("app[a&&b].log").unwrap().is_match("app&.log"));
+        assert!(glob_regex("app[--x].log").unwrap().is_match("app-.log"));
+        assert!(glob_regex("app[--x].log").unwrap().is_match("appa.log"));
+        assert!(glob_regex("app[a~~b[].log").unwrap().is_match("app[.log"));
+        assert!(glob_regex("app[a-].log").unwrap().is_match("app-.log"));
+        assert!(!glob_regex("app[a-c].log").unwrap().is_match("app-.log"));
+        assert!(glob_regex("app[a-c-e].log").unwrap().is_match("app-.log"));
+        assert!(!glob_regex("app[a-c-e].log").unwrap().is_match("appd.log"));
+        assert!(RuleSet::parse("bad", "[[rule]]\nname = \"x\"\naction = \"remove\"\nglob = \"app[log\"").is_err());
+        // Regexes match the whole path, not just the file name
+        let regex_rule = |regex: &str| RuleSet::parse("regex", &format!("[[rule]]\nname = \"x\"\naction = \"remove\"\nregex = '{}'", regex)).unwrap();
+        assert_eq!(regex_rule("^app").decide(&kept_log, 7).unwrap(), Action::Unchanged);
+        assert_eq!(regex_rule("test_rules_dir/app-").decide(&kept_log, 7).unwrap(), Action::Remove);
+        assert_eq!(regex_rule("/app-[0-9]+\\.log$").explain(&kept_log, 7).unwrap().rules[0].conditions[0].actual, kept_log.to_str().unwrap());
+
+        // The classic preset keeps the original bucketing
+        let classic = RuleSet::preset("classic").unwrap();
+        assert_eq!(classic.decide(&old_archive, 7).unwrap(), Action::Remove);
+        assert_eq!(archive_remove_truncate_file_bucketing(old_archive.to_str().unwrap(), 7).unwrap(), 1);
+        assert_eq!(classic.decide(&old_log, 7).unwrap(), Action::Truncate);
+        assert_eq!(archive_remove_truncate_file_bucketing(old_log.to_str().unwrap(), 7).unwrap(), 2);
+
+        let options = RunOptions { rules: Some(std::sync::Arc::new(rules)), ..Default::default() };
+        let files = vec![old_log.clone(), kept_log.clone(), old_archive.clone()];
+        let plan = plan_run(&files, 7, &ArchiveType::TarGunzip, &options);
+        assert_eq!(plan.iter().map(|planned| planned.action).collect::<Vec<_>>(), [Action::Archive, Action::Unchanged, Action::Remove]);
+        assert_eq!(plan[0].decided_by.as_deref(), Some("archive-old-logs"));
+        let report = actual_run(files, 7, ArchiveType::TarGunzip, &options);
+        assert_eq!(report.iter().map(|record| record.action).collect::<Vec<_>>(), [Action::Archive, Action::Unchanged, Action::Remove]);
+        assert!(report[0].archive.as_ref().unwrap().exists());
+        assert!(!old_archive.exists());
+
+        // A catch-all rule never reaches the manifest, catalog, state or temp files
+        let manifest_options = RunOptions { manifest: true, ..Default::default() };
+        fs::write(&kept_log, "kept\n").unwrap();
+        archive_file(kept_log.to_str().unwrap(), ArchiveType::Gzip, &manifest_options).unwrap();
+        let temp_file = path::Path::new(test_dir).join(".stale.gz.logrotate-tmp");
+        fs::write(&temp_file, "partial").unwrap();
+        let manifest = path::Path::new(test_dir).join(".logrotate-manifest");
+        let catalog = path::Path::new(test_dir).join(".logrotate-catalog");
+        let manifest_before = fs::read(&manifest).unwrap();
+        let truncate_all = RuleSet::parse("truncate-all", "[[rule]]\nname = \"everything\"\naction = \"truncate\"\nglob = \"*\"").unwrap();
+        let options = RunOptions { rules: Some(std::sync::Arc::new(truncate_all)), ..Default::default() };
+        let file_list = gather_files_from_directory(test_dir).unwrap();
+        assert!(!file_list.contains(&manifest) && !file_list.contains(&catalog) && !file_list.contains(&temp_file));
+        actual_run(file_list, 7, ArchiveType::Gzip, &options);
+        let report = actual_run(vec![manifest.clone(), catalog.clone(), temp_file.clone()], 7, ArchiveType::Gzip, &options);
+        assert!(report.iter().all(|record| record.action == Action::Unchanged));
+        assert_eq!(fs::read(&manifest).unwrap(), manifest_before);
+        assert!(fs::metadata(&catalog).unwrap().len() > 0);
+        assert_eq!(fs::read_to_string(&temp_file).unwrap(), "partial");
+        assert_eq!(fs::metadata(&kept_log).unwrap().len(), 0);
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_parallel_compression() {
+        let test_dir = "./tests/test_parallel_dir";
+        fs::create_dir_all(test_dir).unwrap();
+        let source_path = path::Path::new(test_dir).join("large.log");
+        let content: String = (0..250_000).map(|line| format!("{:08} request served in {} ms\n", line, line % 977)).collect();
+        assert!(content.len() > 2 * GZIP_BLOCK_SIZE);
+
+        let parallel = ParallelCompression { threshold: 1024, threads: 4 };
+        assert_eq!(parallel.threads_for(1023), None);
+        assert_eq!(parallel.threads_for(1024), Some(4));
+        let options = RunOptions { parallel_compression: Some(parallel), verify: true, ..Default::default() };
+
+        // zstd and xz split work internally - a smaller input keeps the test quick
+        for (archive_type, length) in [
+            (ArchiveType::Gzip, content.len()),
+            (ArchiveType::TarGunzip, content.len()),
+            (ArchiveType::Zstd, 1 << 20),
+            (ArchiveType::Xz, 1 << 20),
+        ] {
+            let content = &content[..length];
+            fs::write(&source_path, content).unwrap();
+            let archive = archive_selection_and_process(source_path.to_str().unwrap(), archive_type.clone(), &options).unwrap();
+            let digests = archive_entry_digests(path::Path::new(&archive)).unwrap();
+            assert_eq!(digests[0].length, content.len() as u64);
+
+            if archive_type == ArchiveType::Gzip {
+                // Several members - a single member decoder stops after the first block
+                let first_member = std::io::read_to_string(flate2::read::GzDecoder::new(fs::File::open(&archive).unwrap())).unwrap();
+                assert_eq!(first_member.len(), GZIP_BLOCK_SIZE);
+                assert_eq!(digests[0].name, "large.log");
+            }
+        }
+
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
     #[test]
     fn test_simple_add() {
         let testing_operand = test_add(1, 2);